@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use clap::Parser;
+use simulator::{
+    ground_station::{run, Command, GroundStationOptions, COMMAND_HELP},
+    io::protocol::SigningConfig,
+};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::TcpListener,
+};
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Stand-in Serpe ground station for local testing")]
+struct Args {
+    /// `host:port` to listen on
+    #[arg(long, default_value = "127.0.0.1:8000", value_name = "ADDRESS")]
+    address: String,
+
+    /// Send a mission to every idle drone this often, in seconds
+    #[arg(long, value_name = "SECONDS", value_parser = parse_interval)]
+    mission_interval: Option<f64>,
+
+    /// How far from the drone scheduled missions go, in metres
+    #[arg(long, default_value_t = 500.0)]
+    mission_distance: f64,
+
+    /// Also print every heartbeat and mission update
+    #[arg(long)]
+    verbose: bool,
+
+    /// Dial a drone listening on `host:port`, can be repeated
+    #[arg(long, value_name = "ADDRESS")]
+    connect: Vec<String>,
+
+    /// Sign frames with this 64-hex-digit key and reject unsigned ones
+    #[arg(long, value_name = "KEY", value_parser = parse_signing_key)]
+    signing_key: Option<SigningConfig>,
+}
+
+fn parse_interval(value: &str) -> Result<f64, String> {
+    let seconds: f64 = value
+        .parse()
+        .map_err(|_| format!("`{}` is not a number", value))?;
+    if !(seconds.is_finite() && seconds > 0.0) {
+        return Err("must be a positive number of seconds".to_string());
+    }
+    Ok(seconds)
+}
+
+fn parse_signing_key(value: &str) -> Result<SigningConfig, String> {
+    let signing = SigningConfig {
+        key: value.to_string(),
+        ..Default::default()
+    };
+    signing.secret()?;
+    Ok(signing)
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+
+    let listener = match TcpListener::bind(&args.address).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("Failed to listen on {}: {}", args.address, err);
+            std::process::exit(1);
+        }
+    };
+    println!("Listening on {}", args.address);
+    println!("{}", COMMAND_HELP);
+
+    let (command_sender, command_receiver) = tokio::sync::mpsc::channel(16);
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match line.parse::<Command>() {
+                Ok(command) => {
+                    if command_sender.send(command).await.is_err() {
+                        break;
+                    }
+                }
+                Err(err) => println!("{}\n{}", err, COMMAND_HELP),
+            }
+        }
+    });
+
+    let token = CancellationToken::new();
+    let signal_token = token.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            signal_token.cancel();
+        }
+    });
+
+    let options = GroundStationOptions {
+        mission_interval: args.mission_interval.map(Duration::from_secs_f64),
+        mission_distance: args.mission_distance,
+        verbose: args.verbose,
+        connect: args.connect,
+        signing: args.signing_key,
+    };
+    run(listener, command_receiver, options, token).await;
+}
@@ -0,0 +1,69 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use crate::misc::clock::{DEFAULT_TICK_RATE, MAX_TIME_SCALE, MIN_TIME_SCALE};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Serpe drone simulator")]
+pub struct Cli {
+    /// Run without a window, only the simulation and IO systems
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Number of drones to spawn, turn on and connect at startup
+    #[arg(long, default_value_t = 0)]
+    pub drones: u32,
+
+    /// Ground station `host:port`, overrides the config file and environment
+    #[arg(long, value_name = "ADDRESS")]
+    pub ground_station: Option<String>,
+
+    /// Path to a TOML config file (defaults to `simulator.toml` if present)
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Path to a TOML scenario describing the drones to spawn at startup
+    #[arg(long, value_name = "PATH")]
+    pub scenario: Option<PathBuf>,
+
+    /// Simulated seconds per real second, between 0.1 and 50
+    #[arg(long, default_value_t = 1.0, value_parser = parse_time_scale)]
+    pub time_scale: f64,
+
+    /// Start with the simulation clock paused
+    #[arg(long)]
+    pub paused: bool,
+
+    /// Simulation ticks per simulated second
+    #[arg(long, default_value_t = DEFAULT_TICK_RATE, value_parser = parse_tick_rate)]
+    pub tick_rate: f64,
+
+    /// Seed for every random choice the simulation makes, overrides the
+    /// scenario's. Random if neither sets one
+    #[arg(long)]
+    pub seed: Option<u64>,
+}
+
+fn parse_tick_rate(value: &str) -> Result<f64, String> {
+    let rate: f64 = value
+        .parse()
+        .map_err(|_| format!("`{}` is not a number", value))?;
+    if !(rate.is_finite() && rate > 0.0) {
+        return Err("must be a positive number of ticks per second".to_string());
+    }
+    Ok(rate)
+}
+
+fn parse_time_scale(value: &str) -> Result<f64, String> {
+    let scale: f64 = value
+        .parse()
+        .map_err(|_| format!("`{}` is not a number", value))?;
+    if !(MIN_TIME_SCALE..=MAX_TIME_SCALE).contains(&scale) {
+        return Err(format!(
+            "must be between {} and {}",
+            MIN_TIME_SCALE, MAX_TIME_SCALE
+        ));
+    }
+    Ok(scale)
+}
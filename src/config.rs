@@ -0,0 +1,81 @@
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    cli::Cli,
+    domain::{connection::ConnectionPolicy, drone::Drone},
+};
+
+/// Config file read from the working directory when `--config` is not given.
+pub const DEFAULT_CONFIG_PATH: &str = "simulator.toml";
+
+/// Overrides the ground-station address from the config file.
+pub const GROUND_STATION_ENV_VAR: &str = "SERPE_GROUND_STATION";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub ground_station: GroundStationConfig,
+    /// Defaults for drones that don't set their own policy.
+    pub connection: ConnectionPolicy,
+}
+
+#[derive(Clone, Debug, Deserialize, Resource)]
+#[serde(default)]
+pub struct GroundStationConfig {
+    /// `host:port` drones connect to unless they have their own override.
+    pub address: String,
+}
+
+impl Default for GroundStationConfig {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1:8000".to_string(),
+        }
+    }
+}
+
+impl GroundStationConfig {
+    /// The address a drone should connect to, honouring its own override.
+    pub fn address_for<'a>(&'a self, drone: &'a Drone) -> &'a str {
+        drone.ground_station.as_deref().unwrap_or(&self.address)
+    }
+}
+
+impl Config {
+    /// Builds the configuration, from lowest to highest priority: defaults,
+    /// the config file, environment variables and command line flags.
+    pub fn load(cli: &Cli) -> Result<Self, String> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None => {
+                let path = PathBuf::from(DEFAULT_CONFIG_PATH);
+                if path.exists() {
+                    Self::from_file(&path)?
+                } else {
+                    Self::default()
+                }
+            }
+        };
+
+        if let Ok(address) = std::env::var(GROUND_STATION_ENV_VAR) {
+            config.ground_station.address = address;
+        }
+
+        if let Some(address) = &cli.ground_station {
+            config.ground_station.address = address.clone();
+        }
+
+        config.connection.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+
+        toml::from_str(&contents).map_err(|err| format!("invalid {}: {}", path.display(), err))
+    }
+}
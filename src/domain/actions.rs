@@ -0,0 +1,165 @@
+use core::fmt;
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+
+use crate::{config::GroundStationConfig, io::IOResource, misc::rng::SimulationRng};
+
+use super::{
+    connection::{
+        connect_drone, disconnect_drone, Connection, ConnectionPolicy, ConnectionStatus,
+        DisconnectReason, DroneDisconnected, PendingConnection, Reconnecting,
+    },
+    coordinates::Coordinates,
+    drone::{AutoConnect, ConnectionState, Drone, DroneState},
+    mission::{Mission, MissionUpload},
+};
+
+/// Something a user (or a script) can do to a drone.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DroneAction {
+    TurnOn,
+    /// Unregisters first if the drone is connected.
+    TurnOff,
+    Connect,
+    /// Also cancels a pending connection or reconnect.
+    Disconnect,
+    Delete,
+    /// Moves the drone to the given latitude and longitude, keeping its
+    /// altitude.
+    Teleport(Coordinates),
+}
+
+impl fmt::Display for DroneAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DroneAction::TurnOn => write!(f, "Turn On"),
+            DroneAction::TurnOff => write!(f, "Turn Off"),
+            DroneAction::Connect => write!(f, "Connect"),
+            DroneAction::Disconnect => write!(f, "Disconnect"),
+            DroneAction::Delete => write!(f, "Delete"),
+            DroneAction::Teleport(_) => write!(f, "Teleport"),
+        }
+    }
+}
+
+/// Asks `system_apply_drone_actions` to perform `action` on `entity`. Every
+/// button acting on drones goes through this event, so anything that can
+/// send events can drive the simulator the same way.
+#[derive(Clone, Debug, Event)]
+pub struct DroneActionRequested {
+    pub entity: Entity,
+    pub action: DroneAction,
+}
+
+#[allow(clippy::type_complexity)]
+pub fn system_apply_drone_actions(
+    mut commands: Commands,
+    mut requests: EventReader<DroneActionRequested>,
+    mut drones_query: Query<(
+        &mut Drone,
+        Option<&Connection>,
+        Option<&ConnectionPolicy>,
+        ConnectionStatus,
+    )>,
+    io_sender: Res<IOResource>,
+    ground_station: Res<GroundStationConfig>,
+    rng: Res<SimulationRng>,
+    mut disconnected_events: EventWriter<DroneDisconnected>,
+) {
+    // Commands only apply after this system, so remember what was already
+    // done this frame instead of acting twice on the same drone
+    let mut deleted = HashSet::new();
+    let mut connecting = HashSet::new();
+
+    for DroneActionRequested { entity, action } in requests.read() {
+        let entity = *entity;
+        if deleted.contains(&entity) {
+            continue;
+        }
+        let Ok((mut drone, connection, policy, status)) = drones_query.get_mut(entity) else {
+            continue;
+        };
+        let state = status.state(connection);
+
+        match action {
+            DroneAction::TurnOn => {
+                drone.state = DroneState::Online;
+            }
+            DroneAction::TurnOff => {
+                if drone.state == DroneState::Offline {
+                    continue;
+                }
+                drone.state = DroneState::Offline;
+
+                if let (Some(connection), Some(policy)) = (connection, policy) {
+                    if state != ConnectionState::Disconnecting {
+                        disconnect_drone(&mut commands, entity, connection, policy);
+                    }
+                }
+                commands.entity(entity).remove::<(
+                    PendingConnection,
+                    Reconnecting,
+                    AutoConnect,
+                    Mission,
+                    MissionUpload,
+                )>();
+            }
+            DroneAction::Connect => {
+                let can_connect = matches!(
+                    state,
+                    ConnectionState::Disconnected
+                        | ConnectionState::Failed { .. }
+                        | ConnectionState::Reconnecting { .. }
+                );
+                if drone.state != DroneState::Online || !can_connect {
+                    continue;
+                }
+                let Some(policy) = policy else {
+                    continue;
+                };
+                if !connecting.insert(entity) {
+                    continue;
+                }
+
+                connect_drone(
+                    &mut commands,
+                    entity,
+                    &drone,
+                    policy,
+                    &io_sender,
+                    &ground_station,
+                    rng.seed(),
+                );
+            }
+            DroneAction::Disconnect => match (connection, policy) {
+                (Some(connection), Some(policy)) => {
+                    if state != ConnectionState::Disconnecting {
+                        disconnect_drone(&mut commands, entity, connection, policy);
+                    }
+                }
+                _ => {
+                    commands
+                        .entity(entity)
+                        .remove::<(PendingConnection, Reconnecting, AutoConnect)>();
+                }
+            },
+            DroneAction::Delete => {
+                if let Some(connection) = connection {
+                    connection.unregister();
+                    disconnected_events.send(DroneDisconnected {
+                        entity,
+                        agent_id: drone.agent_id,
+                        reason: DisconnectReason::Despawned,
+                    });
+                }
+                commands.entity(entity).despawn();
+                deleted.insert(entity);
+            }
+            DroneAction::Teleport(coordinates) => {
+                drone.coordinates.latitude = coordinates.latitude;
+                drone.coordinates.longitude = coordinates.longitude;
+            }
+        }
+    }
+}
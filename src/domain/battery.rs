@@ -0,0 +1,158 @@
+use bevy::prelude::*;
+
+use crate::mavlink::dialects::{serpe_dialect::messages::MissionAbort, SerpeDialect};
+
+use super::{
+    connection::{Connection, Disconnecting, PendingConnection, Reconnecting},
+    drone::{AutoConnect, Drone, DroneState, FlightPhase},
+    mission::{Mission, MissionAbortReason, MissionState, MissionUpload},
+};
+
+const SECONDS_PER_HOUR: f64 = 3600.0;
+
+/// Energy left in a drone, drained by `system_drain_battery` according to
+/// what the drone is doing. Energies are in Wh and powers in W.
+#[derive(Clone, Debug, Component)]
+pub struct Battery {
+    pub capacity: f64,
+    pub charge: f64,
+    /// Fraction of `capacity` below which an ongoing mission is aborted and
+    /// the drone returns to its launch point.
+    pub low_threshold: f64,
+    /// Draw of an online drone sitting on the ground.
+    pub idle_power: f64,
+    /// Draw while taking off or landing.
+    pub climb_power: f64,
+    /// Draw to stay in the air while cruising, before drag.
+    pub hover_power: f64,
+    /// Extra cruising draw per (m/s)², so faster flights cost more.
+    pub drag_coefficient: f64,
+}
+
+impl Default for Battery {
+    fn default() -> Self {
+        Self {
+            capacity: 100.0,
+            charge: 100.0,
+            low_threshold: 0.2,
+            idle_power: 2.0,
+            climb_power: 250.0,
+            hover_power: 180.0,
+            drag_coefficient: 0.5,
+        }
+    }
+}
+
+impl Battery {
+    /// Charge left as a fraction of the capacity, between 0 and 1.
+    pub fn fraction(&self) -> f64 {
+        if self.capacity <= 0.0 {
+            return 0.0;
+        }
+        (self.charge / self.capacity).clamp(0.0, 1.0)
+    }
+
+    pub fn is_low(&self) -> bool {
+        self.fraction() <= self.low_threshold
+    }
+
+    pub fn is_depleted(&self) -> bool {
+        self.charge <= 0.0
+    }
+
+    pub fn recharge(&mut self) {
+        self.charge = self.capacity;
+    }
+
+    /// Current draw of `drone` in W.
+    pub fn power(&self, drone: &Drone) -> f64 {
+        match drone.phase {
+            FlightPhase::Landed if drone.state == DroneState::Online => self.idle_power,
+            FlightPhase::Landed => 0.0,
+            FlightPhase::TakingOff | FlightPhase::Landing => self.climb_power,
+            FlightPhase::Cruising => {
+                self.hover_power + self.drag_coefficient * drone.cruise_speed.powi(2)
+            }
+        }
+    }
+}
+
+pub fn system_attach_battery(
+    mut commands: Commands,
+    new_drones_query: Query<Entity, (Added<Drone>, Without<Battery>)>,
+) {
+    for entity in new_drones_query.iter() {
+        commands.entity(entity).insert(Battery::default());
+    }
+}
+
+pub fn system_drain_battery(time: Res<Time>, mut drones_query: Query<(&Drone, &mut Battery)>) {
+    let hours = time.delta_seconds_f64() / SECONDS_PER_HOUR;
+
+    for (drone, mut battery) in drones_query.iter_mut() {
+        let energy = battery.power(drone) * hours;
+        if energy > 0.0 {
+            battery.charge = (battery.charge - energy).max(0.0);
+        }
+    }
+}
+
+/// Sends drones with a low battery back to their launch point, and shuts
+/// down drones with an empty one: they land where they are, go offline and
+/// drop their link without unregistering.
+#[allow(clippy::type_complexity)]
+pub fn system_battery_failsafe(
+    mut commands: Commands,
+    mut drones_query: Query<(
+        Entity,
+        &mut Drone,
+        &Battery,
+        Option<&mut Mission>,
+        Option<&Connection>,
+    )>,
+) {
+    for (entity, mut drone, battery, mission_opt, connection_opt) in drones_query.iter_mut() {
+        if battery.is_depleted() {
+            if drone.state == DroneState::Offline {
+                continue;
+            }
+
+            println!("Drone {} ran out of battery", drone.agent_id);
+            drone.state = DroneState::Offline;
+            if drone.phase != FlightPhase::Landed {
+                drone.phase = FlightPhase::Landing;
+            }
+            commands.entity(entity).remove::<(
+                Mission,
+                MissionUpload,
+                Connection,
+                PendingConnection,
+                Disconnecting,
+                Reconnecting,
+                AutoConnect,
+            )>();
+            continue;
+        }
+
+        let Some(mut mission) = mission_opt else {
+            continue;
+        };
+        if !battery.is_low() || mission.state != MissionState::Ongoing {
+            continue;
+        }
+
+        println!(
+            "Drone {} is low on battery ({:.0}%), returning to launch",
+            drone.agent_id,
+            battery.fraction() * 100.0
+        );
+        mission.return_to(drone.launch_point);
+        if let Some(connection) = connection_opt {
+            let _ = connection
+                .sender
+                .try_send(SerpeDialect::MissionAbort(MissionAbort {
+                    reason: MissionAbortReason::LowBattery as u8,
+                }));
+        }
+    }
+}
@@ -1,16 +1,664 @@
-use crate::io::{SerpeDialectReceiver, SerpeDialectSender};
-use bevy::prelude::*;
-use mavio::prelude::V2;
-use mavio::{Endpoint, Receiver, Sender};
-use std::net::TcpStream;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
 
-pub type BaseReceiver = Receiver<TcpStream, V2>;
-pub type BaseSender = Sender<TcpStream, V2>;
-pub type BaseEndpoint = Endpoint<V2>;
+use crate::{
+    config::GroundStationConfig,
+    io::{
+        create_connection,
+        error::IoError,
+        impairment::{Impairment, ImpairmentSender},
+        protocol::TamperingSender,
+        ConnectionRequest, ConnectionResultReceiver, IOResource, SerpeDialectReceiver,
+        SerpeDialectSender,
+    },
+    mavlink::dialects::{serpe_dialect::messages::Unregister, SerpeDialect},
+    misc::rng::SimulationRng,
+};
+use bevy::{ecs::query::QueryData, prelude::*};
+use serde::Deserialize;
+use tokio::sync::oneshot::error::TryRecvError;
+
+use super::{
+    drone::{AutoConnect, ConnectionState, Drone, DroneState},
+    mission::{Mission, MissionUpload},
+};
 
 #[derive(Debug, Component)]
 pub struct Connection {
     pub system_id: u8,
     pub receiver: SerpeDialectReceiver,
     pub sender: SerpeDialectSender,
+    /// Receives the error that brought the link down, if any.
+    pub error_receiver: tokio::sync::oneshot::Receiver<IoError>,
+    pub last_error: Option<IoError>,
+    pub health: LinkHealth,
+    /// Hands the drone's `Impairment` to the IO tasks.
+    pub impairment: ImpairmentSender,
+    /// Hands the link faults to the IO tasks, see `Faults::tampering`.
+    pub tampering: TamperingSender,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LinkQuality {
+    #[default]
+    Good,
+    /// Some recent heartbeats went unanswered.
+    Degraded,
+    /// The ground station stopped answering heartbeats altogether.
+    Unresponsive,
+}
+
+/// Heartbeat bookkeeping for a single connection. Acks carry no sequence
+/// number, so each one is matched with the oldest unanswered heartbeat.
+#[derive(Debug, Default)]
+pub struct LinkHealth {
+    outstanding: VecDeque<Instant>,
+    pub last_heartbeat_sent: Option<Instant>,
+    pub last_ack_received: Option<Instant>,
+    /// Round-trip time of the most recently acked heartbeat.
+    pub latency: Option<Duration>,
+    /// Heartbeats that were never acked, over the life of the connection.
+    pub missed_acks: u32,
+    pub consecutive_missed_acks: u32,
+    pub quality: LinkQuality,
+}
+
+impl LinkHealth {
+    pub fn heartbeat_sent(&mut self, now: Instant) {
+        self.outstanding.push_back(now);
+        self.last_heartbeat_sent = Some(now);
+    }
+
+    pub fn ack_received(&mut self, now: Instant) {
+        self.last_ack_received = Some(now);
+        if let Some(sent) = self.outstanding.pop_front() {
+            self.latency = Some(now - sent);
+            self.consecutive_missed_acks = 0;
+        }
+    }
+
+    /// Counts heartbeats older than `ack_timeout` as missed and updates the
+    /// link quality accordingly.
+    pub fn update(&mut self, now: Instant, policy: &ConnectionPolicy) {
+        let ack_timeout = policy.heartbeat_ack_timeout();
+        while let Some(sent) = self.outstanding.front() {
+            if now.duration_since(*sent) < ack_timeout {
+                break;
+            }
+            self.outstanding.pop_front();
+            self.missed_acks += 1;
+            self.consecutive_missed_acks += 1;
+        }
+
+        self.quality = if self.consecutive_missed_acks >= policy.unresponsive_after_missed_acks {
+            LinkQuality::Unresponsive
+        } else if self.consecutive_missed_acks > 0 {
+            LinkQuality::Degraded
+        } else {
+            LinkQuality::Good
+        };
+    }
+}
+
+impl Connection {
+    pub fn is_broken(&self) -> bool {
+        self.receiver.is_closed()
+    }
+
+    /// Queues an `Unregister`. The IO task keeps the socket open until the
+    /// ground station acks it or the drone's unregister timeout passes.
+    pub fn unregister(&self) {
+        let _ = self
+            .sender
+            .try_send(SerpeDialect::Unregister(Unregister {}));
+    }
+}
+
+/// A message the ground station sent to one of the drones.
+#[derive(Debug, Event)]
+pub struct IncomingMessage {
+    pub entity: Entity,
+    pub message: SerpeDialect,
+}
+
+/// A connection attempt that is still in progress on the IO runtime.
+#[derive(Debug, Component)]
+pub struct PendingConnection {
+    pub receiver: ConnectionResultReceiver,
+}
+
+/// The last connection attempt failed, kept around so the UI can show why.
+#[derive(Debug, Component)]
+pub struct FailedConnection {
+    pub error: IoError,
+}
+
+/// An `Unregister` was sent and the drone is waiting for the ack.
+#[derive(Debug, Component)]
+pub struct Disconnecting {
+    pub timer: Timer,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DisconnectReason {
+    /// The ground station acknowledged the `Unregister`.
+    Acknowledged,
+    /// No `UnregisterAck` arrived in time.
+    TimedOut,
+    /// The drone was deleted; the IO task finishes the handshake on its own.
+    Despawned,
+}
+
+#[derive(Debug, Event)]
+pub struct DroneDisconnected {
+    pub entity: Entity,
+    pub agent_id: u32,
+    pub reason: DisconnectReason,
+}
+
+/// Sent whenever connecting fails or an established link goes down.
+#[derive(Debug, Event)]
+pub struct ConnectionError {
+    pub entity: Entity,
+    pub agent_id: u32,
+    pub error: IoError,
+}
+
+/// How a drone connects to its ground station and what it does once the
+/// link is lost. Durations are in seconds.
+#[derive(Clone, Debug, Component, Deserialize)]
+#[serde(default)]
+pub struct ConnectionPolicy {
+    /// Time allowed for opening the link and, separately, for the
+    /// `RegisterAck`. A drone waiting for the ground station to dial in
+    /// waits as long as it takes.
+    pub handshake_timeout: f32,
+    /// Time allowed for the `UnregisterAck` before the socket is closed anyway.
+    pub unregister_timeout: f32,
+    /// A heartbeat without an ack after this long counts as missed.
+    pub heartbeat_ack_timeout: f32,
+    /// Consecutive missed acks before the link is shown as broken.
+    pub unresponsive_after_missed_acks: u32,
+    /// Whether to reconnect automatically after the link drops.
+    pub reconnect: bool,
+    pub initial_backoff: f32,
+    pub max_backoff: f32,
+    pub backoff_multiplier: f32,
+    /// Attempts before giving up, `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ConnectionPolicy {
+    fn default() -> Self {
+        Self {
+            handshake_timeout: 5.0,
+            unregister_timeout: 2.0,
+            heartbeat_ack_timeout: 3.0,
+            unresponsive_after_missed_acks: 5,
+            reconnect: true,
+            initial_backoff: 1.0,
+            max_backoff: 30.0,
+            backoff_multiplier: 2.0,
+            max_attempts: Some(10),
+        }
+    }
+}
+
+impl ConnectionPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("handshake_timeout", self.handshake_timeout),
+            ("unregister_timeout", self.unregister_timeout),
+            ("heartbeat_ack_timeout", self.heartbeat_ack_timeout),
+            ("initial_backoff", self.initial_backoff),
+            ("max_backoff", self.max_backoff),
+        ] {
+            // `try_from_secs_f32` rules out NaN, infinity and negative values
+            if value == 0.0 || Duration::try_from_secs_f32(value).is_err() {
+                return Err(format!(
+                    "connection {} must be a positive number of seconds",
+                    name
+                ));
+            }
+        }
+        if !self.backoff_multiplier.is_finite() || self.backoff_multiplier < 1.0 {
+            return Err("connection backoff_multiplier must be at least 1".to_string());
+        }
+        if self.unresponsive_after_missed_acks == 0 {
+            return Err("connection unresponsive_after_missed_acks must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn handshake_timeout(&self) -> Duration {
+        Duration::from_secs_f32(self.handshake_timeout)
+    }
+
+    pub fn unregister_timeout(&self) -> Duration {
+        Duration::from_secs_f32(self.unregister_timeout)
+    }
+
+    pub fn heartbeat_ack_timeout(&self) -> Duration {
+        Duration::from_secs_f32(self.heartbeat_ack_timeout)
+    }
+
+    /// Delay before reconnect attempt number `attempt` (starting at 1).
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1) as i32;
+        let delay = self.initial_backoff * self.backoff_multiplier.powi(exponent);
+        Duration::from_secs_f32(delay.min(self.max_backoff))
+    }
+}
+
+/// Policy given to drones that were spawned without one.
+#[derive(Default, Resource)]
+pub struct DefaultConnectionPolicy(pub ConnectionPolicy);
+
+/// The link was lost and the drone is waiting to try again.
+#[derive(Debug, Component)]
+pub struct Reconnecting {
+    /// Attempts made so far.
+    pub attempt: u32,
+    /// Counts down the backoff while no attempt is in flight.
+    pub timer: Timer,
+    /// System ID the ground station had assigned before the link dropped.
+    pub previous_system_id: Option<u8>,
+}
+
+#[derive(QueryData)]
+pub struct ConnectionStatus {
+    disconnecting: Option<&'static Disconnecting>,
+    pending: Option<&'static PendingConnection>,
+    failed: Option<&'static FailedConnection>,
+    reconnecting: Option<&'static Reconnecting>,
+}
+
+impl ConnectionStatusItem<'_> {
+    pub fn state(&self, connection: Option<&Connection>) -> ConnectionState {
+        match connection {
+            Some(_) if self.disconnecting.is_some() => ConnectionState::Disconnecting,
+            Some(connection) if connection.is_broken() => ConnectionState::Broken,
+            Some(connection) => match connection.health.quality {
+                LinkQuality::Good => ConnectionState::Connected,
+                LinkQuality::Degraded => ConnectionState::Degraded,
+                LinkQuality::Unresponsive => ConnectionState::Broken,
+            },
+            None => {
+                if let Some(reconnecting) = self.reconnecting {
+                    ConnectionState::Reconnecting {
+                        attempt: reconnecting.attempt,
+                    }
+                } else if self.pending.is_some() {
+                    ConnectionState::Connecting
+                } else if let Some(failed) = self.failed {
+                    ConnectionState::Failed {
+                        reason: failed.error.to_string(),
+                    }
+                } else {
+                    ConnectionState::Disconnected
+                }
+            }
+        }
+    }
+}
+
+/// Starts connecting `drone` to its ground station without blocking.
+pub fn connect_drone(
+    commands: &mut Commands,
+    entity: Entity,
+    drone: &Drone,
+    policy: &ConnectionPolicy,
+    io_sender: &IOResource,
+    ground_station: &GroundStationConfig,
+    seed: u64,
+) {
+    let address = ground_station.address_for(drone).to_string();
+    let mut entity_commands = commands.entity(entity);
+    entity_commands.remove::<FailedConnection>();
+
+    let request = ConnectionRequest {
+        agent_id: drone.agent_id,
+        address,
+        transport: drone.transport.clone(),
+        protocol: drone.protocol.clone(),
+        handshake_timeout: policy.handshake_timeout(),
+        unregister_timeout: policy.unregister_timeout(),
+        coordinates: drone.coordinates,
+        seed,
+    };
+    match create_connection(request, io_sender) {
+        Ok(receiver) => {
+            entity_commands.insert(PendingConnection { receiver });
+        }
+        Err(error) => {
+            entity_commands.insert(FailedConnection { error });
+        }
+    }
+}
+
+/// Starts the `Unregister` handshake, `system_disconnect` finishes it.
+pub fn disconnect_drone(
+    commands: &mut Commands,
+    entity: Entity,
+    connection: &Connection,
+    policy: &ConnectionPolicy,
+) {
+    connection.unregister();
+    commands
+        .entity(entity)
+        .remove::<(Reconnecting, AutoConnect)>()
+        .insert(Disconnecting {
+            timer: Timer::new(policy.unregister_timeout(), TimerMode::Once),
+        });
+}
+
+pub fn system_attach_connection_policy(
+    mut commands: Commands,
+    default_policy: Res<DefaultConnectionPolicy>,
+    new_drones_query: Query<Entity, (Added<Drone>, Without<ConnectionPolicy>)>,
+) {
+    for entity in new_drones_query.iter() {
+        commands.entity(entity).insert(default_policy.0.clone());
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn system_auto_connect(
+    mut commands: Commands,
+    io_sender: Res<IOResource>,
+    ground_station: Res<GroundStationConfig>,
+    rng: Res<SimulationRng>,
+    drones_query: Query<
+        (Entity, &Drone, &ConnectionPolicy),
+        (
+            With<AutoConnect>,
+            Without<Connection>,
+            Without<PendingConnection>,
+        ),
+    >,
+) {
+    for (entity, drone, policy) in drones_query.iter() {
+        if drone.state != DroneState::Online {
+            continue;
+        }
+
+        commands.entity(entity).remove::<AutoConnect>();
+        connect_drone(
+            &mut commands,
+            entity,
+            drone,
+            policy,
+            &io_sender,
+            &ground_station,
+            rng.seed(),
+        );
+    }
+}
+
+/// Moves everything the ground stations sent since the last frame into
+/// `IncomingMessage` events.
+pub fn system_receive_messages(
+    mut connection_query: Query<(Entity, &mut Connection)>,
+    mut incoming_messages: EventWriter<IncomingMessage>,
+) {
+    for (entity, mut connection) in connection_query.iter_mut() {
+        while let Ok(message) = connection.receiver.try_recv() {
+            incoming_messages.send(IncomingMessage { entity, message });
+        }
+    }
+}
+
+pub fn system_poll_pending_connections(
+    mut commands: Commands,
+    mut pending_query: Query<(Entity, &Drone, &mut PendingConnection)>,
+    mut connection_errors: EventWriter<ConnectionError>,
+) {
+    for (entity, drone, mut pending) in pending_query.iter_mut() {
+        let result = match pending.receiver.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => continue,
+            Err(TryRecvError::Closed) => Err(IoError::ChannelClosed),
+        };
+
+        let mut entity_commands = commands.entity(entity);
+        entity_commands.remove::<PendingConnection>();
+
+        match result {
+            Ok(connection) => {
+                entity_commands.insert(connection);
+            }
+            Err(error) => {
+                entity_commands.insert(FailedConnection {
+                    error: error.clone(),
+                });
+                connection_errors.send(ConnectionError {
+                    entity,
+                    agent_id: drone.agent_id,
+                    error,
+                });
+            }
+        }
+    }
+}
+
+pub fn system_poll_connection_errors(
+    mut connection_query: Query<(Entity, &Drone, &mut Connection)>,
+    mut connection_errors: EventWriter<ConnectionError>,
+) {
+    for (entity, drone, mut connection) in connection_query.iter_mut() {
+        let error = match connection.error_receiver.try_recv() {
+            Ok(error) => error,
+            Err(_) => continue,
+        };
+
+        connection.last_error = Some(error.clone());
+        connection_errors.send(ConnectionError {
+            entity,
+            agent_id: drone.agent_id,
+            error,
+        });
+    }
+}
+
+pub fn system_link_health(mut connection_query: Query<(&ConnectionPolicy, &mut Connection)>) {
+    let now = Instant::now();
+    for (policy, mut connection) in connection_query.iter_mut() {
+        connection.health.update(now, policy);
+    }
+}
+
+pub fn system_attach_impairment(
+    mut commands: Commands,
+    new_drones_query: Query<Entity, (Added<Drone>, Without<Impairment>)>,
+) {
+    for entity in new_drones_query.iter() {
+        commands.entity(entity).insert(Impairment::default());
+    }
+}
+
+/// Passes edited impairments, and the current one on every new link, to the
+/// IO tasks.
+#[allow(clippy::type_complexity)]
+pub fn system_apply_impairment(
+    connection_query: Query<
+        (&Impairment, &Connection),
+        Or<(Changed<Impairment>, Added<Connection>)>,
+    >,
+) {
+    for (impairment, connection) in connection_query.iter() {
+        connection.impairment.send_replace(impairment.clone());
+    }
+}
+
+/// Drops links whose IO tasks have ended and, if the drone's policy allows
+/// it, schedules a reconnect.
+pub fn system_handle_lost_connections(
+    mut commands: Commands,
+    connection_query: Query<
+        (Entity, &Drone, &ConnectionPolicy, &Connection),
+        Without<Disconnecting>,
+    >,
+) {
+    for (entity, drone, policy, connection) in connection_query.iter() {
+        if !connection.is_broken() {
+            continue;
+        }
+
+        let error = connection
+            .last_error
+            .clone()
+            .unwrap_or(IoError::ChannelClosed);
+
+        let mut entity_commands = commands.entity(entity);
+        entity_commands
+            .remove::<(Connection, MissionUpload)>()
+            .insert(FailedConnection { error });
+
+        if policy.reconnect && drone.state == DroneState::Online {
+            println!(
+                "Drone {} lost its connection, reconnecting in {:.1}s",
+                drone.agent_id,
+                policy.backoff(1).as_secs_f32()
+            );
+            entity_commands.insert(Reconnecting {
+                attempt: 0,
+                timer: Timer::new(policy.backoff(1), TimerMode::Once),
+                previous_system_id: Some(connection.system_id),
+            });
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn system_reconnect(
+    mut commands: Commands,
+    time: Res<Time<Real>>,
+    io_sender: Res<IOResource>,
+    ground_station: Res<GroundStationConfig>,
+    rng: Res<SimulationRng>,
+    mut reconnecting_query: Query<(
+        Entity,
+        &Drone,
+        &ConnectionPolicy,
+        &mut Reconnecting,
+        Option<&Connection>,
+        Has<PendingConnection>,
+    )>,
+) {
+    for (entity, drone, policy, mut reconnecting, connection, pending) in
+        reconnecting_query.iter_mut()
+    {
+        if let Some(connection) = connection {
+            if reconnecting.previous_system_id != Some(connection.system_id) {
+                println!(
+                    "Drone {} reconnected with new system id {}",
+                    drone.agent_id, connection.system_id
+                );
+            }
+            commands.entity(entity).remove::<Reconnecting>();
+            continue;
+        }
+
+        if drone.state != DroneState::Online {
+            commands.entity(entity).remove::<Reconnecting>();
+            continue;
+        }
+
+        // Wait for the attempt in flight before counting down the next one
+        if pending {
+            continue;
+        }
+
+        if !reconnecting.timer.tick(time.delta()).finished() {
+            continue;
+        }
+
+        if policy
+            .max_attempts
+            .is_some_and(|max_attempts| reconnecting.attempt >= max_attempts)
+        {
+            println!(
+                "Drone {} gave up reconnecting after {} attempts",
+                drone.agent_id, reconnecting.attempt
+            );
+            commands.entity(entity).remove::<Reconnecting>();
+            continue;
+        }
+
+        reconnecting.attempt += 1;
+        let backoff = policy.backoff(reconnecting.attempt + 1);
+        reconnecting.timer = Timer::new(backoff, TimerMode::Once);
+
+        connect_drone(
+            &mut commands,
+            entity,
+            drone,
+            policy,
+            &io_sender,
+            &ground_station,
+            rng.seed(),
+        );
+    }
+}
+
+pub fn system_disconnect(
+    mut commands: Commands,
+    time: Res<Time<Real>>,
+    mut incoming_messages: EventReader<IncomingMessage>,
+    mut disconnecting_query: Query<(Entity, &Drone, &Connection, &mut Disconnecting)>,
+    mut disconnected_events: EventWriter<DroneDisconnected>,
+) {
+    let acknowledged: Vec<Entity> = incoming_messages
+        .read()
+        .filter(|event| matches!(event.message, SerpeDialect::UnregisterAck(_)))
+        .map(|event| event.entity)
+        .collect();
+
+    for (entity, drone, connection, mut disconnecting) in disconnecting_query.iter_mut() {
+        let reason = if acknowledged.contains(&entity) {
+            DisconnectReason::Acknowledged
+        } else if disconnecting.timer.tick(time.delta()).finished() || connection.is_broken() {
+            DisconnectReason::TimedOut
+        } else {
+            continue;
+        };
+
+        commands
+            .entity(entity)
+            .remove::<(Connection, Disconnecting, Mission, MissionUpload)>();
+        disconnected_events.send(DroneDisconnected {
+            entity,
+            agent_id: drone.agent_id,
+            reason,
+        });
+    }
+}
+
+/// Unregisters every connected drone when the app is closing. `main` then
+/// waits for the IO tasks to finish their handshakes.
+pub fn system_unregister_on_exit(
+    mut exit_events: EventReader<AppExit>,
+    connection_query: Query<&Connection, Without<Disconnecting>>,
+) {
+    if exit_events.read().next().is_none() {
+        return;
+    }
+
+    for connection in connection_query.iter() {
+        connection.unregister();
+    }
+}
+
+pub fn system_log_connection_errors(mut connection_errors: EventReader<ConnectionError>) {
+    for event in connection_errors.read() {
+        println!("Drone {}: {}", event.agent_id, event.error);
+    }
+}
+
+pub fn system_log_disconnections(mut disconnected_events: EventReader<DroneDisconnected>) {
+    for event in disconnected_events.read() {
+        println!("Drone {} disconnected ({:?})", event.agent_id, event.reason);
+    }
 }
@@ -1,8 +1,66 @@
-#[derive(Clone, Copy, Debug, Default)]
+/// A position in WGS84 degrees. This is the only representation used inside
+/// the simulation; the wire format lives in `io::wire` and the screen mapping
+/// in the UI.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct Coordinates {
-    pub latitude: f32,
-    pub longitude: f32,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Metres above the ground.
+    pub altitude: f64,
 }
 
+/// Where new drones are placed when no position is given.
+pub const DEFAULT_COORDINATES: Coordinates = Coordinates {
+    longitude: -9.114488884434095,
+    latitude: 38.75600095957655,
+    altitude: 0.0,
+};
 
-pub const COORDS_ZOOM: f32 = 1000.0;
+/// Mean Earth radius in metres, as used by the haversine formula.
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+
+impl Coordinates {
+    /// Great-circle distance in metres (haversine), ignoring altitude.
+    pub fn distance_to(&self, other: &Coordinates) -> f64 {
+        let lat1 = self.latitude.to_radians();
+        let lat2 = other.latitude.to_radians();
+        let delta_lat = lat2 - lat1;
+        let delta_lon = (other.longitude - self.longitude).to_radians();
+
+        let a = (delta_lat / 2.0).sin().powi(2)
+            + lat1.cos() * lat2.cos() * (delta_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS * a.sqrt().atan2((1.0 - a).sqrt())
+    }
+
+    /// Initial bearing towards `other` in radians, clockwise from true north.
+    pub fn bearing_to(&self, other: &Coordinates) -> f64 {
+        let lat1 = self.latitude.to_radians();
+        let lat2 = other.latitude.to_radians();
+        let delta_lon = (other.longitude - self.longitude).to_radians();
+
+        let y = delta_lon.sin() * lat2.cos();
+        let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * delta_lon.cos();
+        y.atan2(x)
+    }
+
+    /// The point reached after travelling `distance` metres along the great
+    /// circle that starts here with the given `bearing` (radians), keeping
+    /// the current altitude.
+    pub fn destination(&self, bearing: f64, distance: f64) -> Coordinates {
+        let lat1 = self.latitude.to_radians();
+        let lon1 = self.longitude.to_radians();
+        let angular = distance / EARTH_RADIUS;
+
+        let lat2 = (lat1.sin() * angular.cos() + lat1.cos() * angular.sin() * bearing.cos()).asin();
+        let lon2 = lon1
+            + (bearing.sin() * angular.sin() * lat1.cos())
+                .atan2(angular.cos() - lat1.sin() * lat2.sin());
+
+        Coordinates {
+            latitude: lat2.to_degrees(),
+            // Normalise to [-180, 180)
+            longitude: (lon2.to_degrees() + 540.0) % 360.0 - 180.0,
+            altitude: self.altitude,
+        }
+    }
+}
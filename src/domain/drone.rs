@@ -1,9 +1,18 @@
 use bevy::prelude::*;
 use core::fmt;
 
+use crate::io::{protocol::ProtocolConfig, transport::TransportConfig};
+
 use super::coordinates::Coordinates;
 
-#[derive(Debug, PartialEq)]
+/// Cruise speed of newly created drones, in metres per second.
+pub const DEFAULT_CRUISE_SPEED: f64 = 15.0;
+/// Altitude newly created drones climb to before flying a mission, in metres.
+pub const DEFAULT_CRUISE_ALTITUDE: f64 = 30.0;
+/// Vertical speed of newly created drones, in metres per second.
+pub const DEFAULT_CLIMB_RATE: f64 = 3.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum DroneState {
     Offline,
     Online,
@@ -18,19 +27,52 @@ impl fmt::Display for DroneState {
     }
 }
 
+/// Where a drone is in its flight. Missions take off from `Landed`, cruise
+/// through every waypoint and land on the last one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlightPhase {
+    Landed,
+    TakingOff,
+    Cruising,
+    Landing,
+}
+
+impl fmt::Display for FlightPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlightPhase::Landed => write!(f, "Landed"),
+            FlightPhase::TakingOff => write!(f, "Taking Off"),
+            FlightPhase::Cruising => write!(f, "Cruising"),
+            FlightPhase::Landing => write!(f, "Landing"),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum ConnectionState {
     Disconnected,
+    Connecting,
     Connected,
+    Degraded,
+    Disconnecting,
     Broken,
+    Reconnecting { attempt: u32 },
+    Failed { reason: String },
 }
 
 impl fmt::Display for ConnectionState {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ConnectionState::Disconnected => write!(f, "Disconnected"),
+            ConnectionState::Connecting => write!(f, "Connecting…"),
             ConnectionState::Connected => write!(f, "Connected"),
+            ConnectionState::Degraded => write!(f, "Degraded"),
+            ConnectionState::Disconnecting => write!(f, "Disconnecting…"),
             ConnectionState::Broken => write!(f, "Broken"),
+            ConnectionState::Reconnecting { attempt } => {
+                write!(f, "Reconnecting (attempt {})", attempt)
+            }
+            ConnectionState::Failed { reason } => write!(f, "Failed ({})", reason),
         }
     }
 }
@@ -40,8 +82,46 @@ pub struct Drone {
     pub agent_id: u32,
     pub state: DroneState,
     pub coordinates: Coordinates,
+    pub phase: FlightPhase,
+    /// Where the drone last took off from, and returns to on low battery.
+    pub launch_point: Coordinates,
+    /// Ground speed while flying a mission, in metres per second.
+    pub cruise_speed: f64,
+    /// Altitude missions are flown at, in metres above the ground.
+    pub cruise_altitude: f64,
+    /// Vertical speed when taking off and landing, in metres per second.
+    pub climb_rate: f64,
+    /// Ground station `host:port` for this drone only, instead of the global one.
+    pub ground_station: Option<String>,
+    /// How the drone reaches its ground station.
+    pub transport: TransportConfig,
+    /// MAVLink version and signing of that link.
+    pub protocol: ProtocolConfig,
+}
+
+impl Drone {
+    pub fn new(agent_id: u32, coordinates: Coordinates) -> Self {
+        Self {
+            agent_id,
+            state: DroneState::Offline,
+            coordinates,
+            phase: FlightPhase::Landed,
+            launch_point: coordinates,
+            cruise_speed: DEFAULT_CRUISE_SPEED,
+            cruise_altitude: DEFAULT_CRUISE_ALTITUDE,
+            climb_rate: DEFAULT_CLIMB_RATE,
+            ground_station: None,
+            transport: TransportConfig::default(),
+            protocol: ProtocolConfig::default(),
+        }
+    }
 }
 
+/// Marks an online drone that should connect to the ground station as soon
+/// as possible, without anyone pressing "Connect".
+#[derive(Debug, Component)]
+pub struct AutoConnect;
+
 #[derive(Bundle)]
 pub struct DroneBundle {
     drone: Drone,
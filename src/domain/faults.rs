@@ -0,0 +1,327 @@
+use core::fmt;
+use std::f64::consts::TAU;
+
+use bevy::prelude::*;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::{io::protocol::Tampering, misc::rng::DroneRng};
+
+use super::{battery::Battery, connection::Connection, coordinates::Coordinates, drone::Drone};
+
+/// How fast a drifting GPS fix wanders off, in metres per second.
+const GPS_DRIFT_SPEED: f64 = 2.0;
+/// Largest change to the drift direction, in radians per second.
+const GPS_DRIFT_TURN_RATE: f64 = 0.5;
+
+/// A way to make a drone misbehave, to see how the ground station copes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fault {
+    /// Empties the battery at once.
+    PowerLoss,
+    /// The reported position wanders away from the real one.
+    GpsDrift,
+    /// The reported position stops updating.
+    GpsFreeze,
+    /// The drone stops moving, whatever its mission says.
+    StuckPosition,
+    /// Missions from the ground station are turned down.
+    RefuseMissions,
+    /// Missions end without a `MissionFinished`.
+    WithholdMissionFinished,
+    /// The socket closes without an `Unregister`.
+    SocketDrop,
+    /// Frames go out without their signature, on a signed link.
+    UnsignedFrames,
+    /// Every frame is followed by a copy of the one sent before it.
+    ReplayedFrames,
+}
+
+impl Fault {
+    pub const ALL: [Fault; 9] = [
+        Fault::PowerLoss,
+        Fault::GpsDrift,
+        Fault::GpsFreeze,
+        Fault::StuckPosition,
+        Fault::RefuseMissions,
+        Fault::WithholdMissionFinished,
+        Fault::SocketDrop,
+        Fault::UnsignedFrames,
+        Fault::ReplayedFrames,
+    ];
+
+    /// Faults that happen once instead of staying on until cleared.
+    pub fn is_one_shot(self) -> bool {
+        matches!(self, Fault::PowerLoss | Fault::SocketDrop)
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::PowerLoss => write!(f, "Power Loss"),
+            Fault::GpsDrift => write!(f, "GPS Drift"),
+            Fault::GpsFreeze => write!(f, "GPS Freeze"),
+            Fault::StuckPosition => write!(f, "Stuck Position"),
+            Fault::RefuseMissions => write!(f, "Refuse Missions"),
+            Fault::WithholdMissionFinished => write!(f, "No MissionFinished"),
+            Fault::SocketDrop => write!(f, "Socket Drop"),
+            Fault::UnsignedFrames => write!(f, "Unsigned Frames"),
+            Fault::ReplayedFrames => write!(f, "Replayed Frames"),
+        }
+    }
+}
+
+/// A fault waiting for its time, see `Faults::schedule`.
+#[derive(Clone, Debug)]
+pub struct ScheduledFault {
+    pub fault: Fault,
+    /// Whether the fault starts or clears. Ignored for one-shot faults.
+    pub active: bool,
+    /// Simulated seconds left before it fires.
+    pub remaining: f64,
+}
+
+/// The faults a drone currently suffers from, and those still to come.
+#[derive(Clone, Debug, Default, Component)]
+pub struct Faults {
+    active: Vec<Fault>,
+    scheduled: Vec<ScheduledFault>,
+    drift_bearing: f64,
+    /// How far the GPS has drifted, in metres.
+    drift_distance: f64,
+    frozen_at: Option<Coordinates>,
+}
+
+impl Faults {
+    pub fn is_active(&self, fault: Fault) -> bool {
+        self.active.contains(&fault)
+    }
+
+    pub fn any_active(&self) -> bool {
+        !self.active.is_empty()
+    }
+
+    pub fn scheduled(&self) -> &[ScheduledFault] {
+        &self.scheduled
+    }
+
+    /// Requests `fault` to start (or clear) `after` simulated seconds.
+    pub fn schedule(&mut self, fault: Fault, active: bool, after: f64) {
+        self.scheduled.push(ScheduledFault {
+            fault,
+            active,
+            remaining: after,
+        });
+    }
+
+    pub fn cancel_scheduled(&mut self, index: usize) {
+        if index < self.scheduled.len() {
+            self.scheduled.remove(index);
+        }
+    }
+
+    /// What the faults do to the frames on the drone's link.
+    pub fn tampering(&self) -> Tampering {
+        Tampering {
+            strip_signatures: self.is_active(Fault::UnsignedFrames),
+            replay: self.is_active(Fault::ReplayedFrames),
+        }
+    }
+
+    /// Where the GPS says a drone at `position` is.
+    pub fn gps_position(&self, position: &Coordinates) -> Coordinates {
+        if let Some(frozen_at) = self.frozen_at {
+            return frozen_at;
+        }
+        if self.is_active(Fault::GpsDrift) {
+            return position.destination(self.drift_bearing, self.drift_distance);
+        }
+        *position
+    }
+
+    fn set_active(
+        &mut self,
+        fault: Fault,
+        active: bool,
+        position: &Coordinates,
+        rng: &mut impl Rng,
+    ) {
+        if active == self.is_active(fault) {
+            return;
+        }
+
+        match (fault, active) {
+            (Fault::GpsDrift, true) => {
+                self.drift_bearing = rng.gen_range(0.0..TAU);
+                self.drift_distance = 0.0;
+            }
+            (Fault::GpsFreeze, true) => self.frozen_at = Some(self.gps_position(position)),
+            (Fault::GpsFreeze, false) => self.frozen_at = None,
+            _ => {}
+        }
+
+        if active {
+            self.active.push(fault);
+        } else {
+            self.active.retain(|other| *other != fault);
+        }
+    }
+}
+
+/// What `drone` tells the ground station its position is.
+pub fn gps_position(drone: &Drone, faults: Option<&Faults>) -> Coordinates {
+    match faults {
+        Some(faults) => faults.gps_position(&drone.coordinates),
+        None => drone.coordinates,
+    }
+}
+
+/// Asks `system_apply_faults` to start or clear `fault` on `entity`. Sent by
+/// the UI, schedules are applied by `system_tick_faults` directly.
+#[derive(Clone, Debug, Event)]
+pub struct FaultRequested {
+    pub entity: Entity,
+    pub fault: Fault,
+    /// Ignored for one-shot faults.
+    pub active: bool,
+}
+
+pub fn system_attach_faults(
+    mut commands: Commands,
+    new_drones_query: Query<Entity, (Added<Drone>, Without<Faults>)>,
+) {
+    for entity in new_drones_query.iter() {
+        commands.entity(entity).insert(Faults::default());
+    }
+}
+
+/// Starts or clears `fault` on a drone, for both requests and schedules.
+fn apply_fault(
+    drone: &Drone,
+    faults: &mut Faults,
+    battery: Option<Mut<Battery>>,
+    connection: Option<Mut<Connection>>,
+    fault: Fault,
+    active: bool,
+    rng: &mut impl Rng,
+) {
+    match fault {
+        Fault::PowerLoss => {
+            // The battery failsafe takes it from there
+            if let Some(mut battery) = battery {
+                println!("Drone {} lost power", drone.agent_id);
+                battery.charge = 0.0;
+            }
+        }
+        Fault::SocketDrop => {
+            // Looks like any other broken link to the rest of the
+            // simulation, so the reconnect policy still applies
+            if let Some(mut connection) = connection {
+                println!("Drone {} dropped its socket", drone.agent_id);
+                connection.receiver.close();
+            }
+        }
+        fault => {
+            println!(
+                "Drone {}: {} {}",
+                drone.agent_id,
+                fault,
+                if active { "started" } else { "cleared" }
+            );
+            faults.set_active(fault, active, &drone.coordinates, rng);
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn system_apply_faults(
+    mut requests: EventReader<FaultRequested>,
+    mut drones_query: Query<(
+        &Drone,
+        &mut Faults,
+        &mut DroneRng,
+        Option<&mut Battery>,
+        Option<&mut Connection>,
+    )>,
+) {
+    for request in requests.read() {
+        let Ok((drone, mut faults, mut rng, battery, connection)) =
+            drones_query.get_mut(request.entity)
+        else {
+            continue;
+        };
+        apply_fault(
+            drone,
+            &mut faults,
+            battery,
+            connection,
+            request.fault,
+            request.active,
+            &mut rng.faults,
+        );
+    }
+}
+
+/// Passes the link faults to the IO tasks, when they change and on every
+/// new link.
+#[allow(clippy::type_complexity)]
+pub fn system_apply_tampering(
+    connection_query: Query<(&Faults, &Connection), Or<(Changed<Faults>, Added<Connection>)>>,
+) {
+    for (faults, connection) in connection_query.iter() {
+        connection.tampering.send_if_modified(|tampering| {
+            let changed = *tampering != faults.tampering();
+            *tampering = faults.tampering();
+            changed
+        });
+    }
+}
+
+/// Moves drifting GPS fixes along and fires scheduled faults when due, on
+/// the same tick so they start at the simulated time they were set for.
+#[allow(clippy::type_complexity)]
+pub fn system_tick_faults(
+    time: Res<Time>,
+    mut drones_query: Query<(
+        &Drone,
+        &mut Faults,
+        &mut DroneRng,
+        Option<&mut Battery>,
+        Option<&mut Connection>,
+    )>,
+) {
+    let delta = time.delta_seconds_f64();
+
+    for (drone, mut faults, mut rng, mut battery, mut connection) in drones_query.iter_mut() {
+        if faults.is_active(Fault::GpsDrift) {
+            let turn = GPS_DRIFT_TURN_RATE * delta;
+            faults.drift_bearing += rng.faults.gen_range(-turn..=turn);
+            faults.drift_distance += GPS_DRIFT_SPEED * delta;
+        }
+
+        if faults.scheduled.is_empty() {
+            continue;
+        }
+        for scheduled in faults.scheduled.iter_mut() {
+            scheduled.remaining -= delta;
+        }
+        let (due, pending) = std::mem::take(&mut faults.scheduled)
+            .into_iter()
+            .partition(|scheduled| scheduled.remaining <= 0.0);
+        faults.scheduled = pending;
+
+        for scheduled in due {
+            apply_fault(
+                drone,
+                &mut faults,
+                battery.as_mut().map(|battery| battery.reborrow()),
+                connection.as_mut().map(|connection| connection.reborrow()),
+                scheduled.fault,
+                scheduled.active,
+                &mut rng.faults,
+            );
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use core::fmt;
+use std::f64::consts::TAU;
+
+use rand::Rng;
+
+use super::coordinates::Coordinates;
+
+/// How a batch of drones is laid out around a centre point. Distances are in
+/// metres.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Formation {
+    /// Rows of equal length, `spacing` apart.
+    Grid { spacing: f64 },
+    /// Evenly spread along a circle.
+    Circle { radius: f64 },
+    /// Uniformly random within a `width` x `height` box.
+    Random { width: f64, height: f64 },
+}
+
+impl fmt::Display for Formation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Formation::Grid { .. } => write!(f, "Grid"),
+            Formation::Circle { .. } => write!(f, "Circle"),
+            Formation::Random { .. } => write!(f, "Random"),
+        }
+    }
+}
+
+impl Formation {
+    /// Positions for `count` drones centred on `centre`.
+    pub fn positions(
+        &self,
+        centre: &Coordinates,
+        count: usize,
+        rng: &mut impl Rng,
+    ) -> Vec<Coordinates> {
+        let offsets: Vec<(f64, f64)> = match *self {
+            Formation::Grid { spacing } => {
+                let columns = (count as f64).sqrt().ceil().max(1.0) as usize;
+                let rows = count.div_ceil(columns);
+                (0..count)
+                    .map(|index| {
+                        let column = (index % columns) as f64 - (columns - 1) as f64 / 2.0;
+                        let row = (index / columns) as f64 - (rows - 1) as f64 / 2.0;
+                        (column * spacing, -row * spacing)
+                    })
+                    .collect()
+            }
+            Formation::Circle { radius } => (0..count)
+                .map(|index| {
+                    let angle = TAU * index as f64 / count as f64;
+                    (radius * angle.sin(), radius * angle.cos())
+                })
+                .collect(),
+            Formation::Random { width, height } => (0..count)
+                .map(|_| {
+                    (
+                        rng.gen_range(-0.5..=0.5) * width,
+                        rng.gen_range(-0.5..=0.5) * height,
+                    )
+                })
+                .collect(),
+        };
+
+        offsets
+            .into_iter()
+            .map(|(east, north)| offset(centre, east, north))
+            .collect()
+    }
+}
+
+/// `centre` moved `east` and `north` metres.
+fn offset(centre: &Coordinates, east: f64, north: f64) -> Coordinates {
+    let distance = east.hypot(north);
+    if distance == 0.0 {
+        return *centre;
+    }
+    centre.destination(east.atan2(north), distance)
+}
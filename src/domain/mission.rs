@@ -3,165 +3,422 @@ use std::time::{Duration, Instant};
 use bevy::prelude::*;
 
 use crate::{
+    io::wire,
     mavlink::dialects::{
-        serpe_dialect::messages::{MissionAccept, MissionUpdate},
+        serpe_dialect::messages::{MissionAccept, MissionAck, MissionFinished, MissionItemRequest},
         SerpeDialect,
     },
+    misc::rng::DroneRng,
 };
 
-use super::{connection::Connection, coordinates::{Coordinates, COORDS_ZOOM}, drone::Drone};
-
-const DRONE_SPEED: f32 = 0.001;
+use super::{
+    connection::{Connection, IncomingMessage},
+    coordinates::Coordinates,
+    drone::{Drone, FlightPhase},
+    faults::{gps_position, Fault, Faults},
+    telemetry::Telemetry,
+};
 
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub enum MissionState {
     AwaitingAcceptAck,
     Ongoing,
     AwaitingFinishedAck,
+    /// Aborted by the drone itself, flying back to where it took off.
+    ReturningToLaunch,
 }
 
+/// How long to wait for a requested mission item before asking again.
+const UPLOAD_ITEM_TIMEOUT: Duration = Duration::from_millis(1500);
+/// Requests for the same item before the upload is abandoned.
+const MAX_UPLOAD_RETRIES: u32 = 3;
+
 #[derive(Clone, Debug, Component)]
 pub struct Mission {
     pub state: MissionState,
-    pub target: Coordinates,
     pub waypoints: Vec<Coordinates>,
+    /// Index into `waypoints` of the one being flown to.
+    pub current_waypoint: usize,
 }
 
-pub fn system_mission_updater(
-    mut drones_query: Query<(Entity, &Drone, &mut Connection, Option<&mut Mission>)>,
-    mut commands: Commands,
-) {
-    for (entity, _, mut connection, mut mission_opt) in drones_query.iter_mut() {
-        while let Ok(message) = connection.receiver.try_recv() {
-            match message {
-                SerpeDialect::MissionRequest(msg) => {
-                    if mission_opt.is_some() {
-                        // ignore if it already has a mission;
-                        continue;
-                    } else {
-                        let _ = connection
-                            .sender
-                            .try_send(SerpeDialect::MissionAccept(MissionAccept {}));
-
-                        commands.entity(entity).insert(Mission {
-                            state: MissionState::AwaitingAcceptAck,
-                            target: Coordinates {
-                                latitude: msg.target_latitude,
-                                longitude: msg.target_longitude,
-                            },
-                            waypoints: vec![], // TODO
-                        });
-                    }
-                }
-                crate::mavlink::dialects::SerpeDialect::MissionAcceptAck(msg) => {
-                    match mission_opt {
-                        Some(ref mut mission)
-                            if mission.state == MissionState::AwaitingAcceptAck =>
-                        {
-                            mission.state = MissionState::Ongoing;
-                        }
-                        Some(_) => {
-                            println!("received an awating accept ack on already going mission");
-                        }
-                        None => {
-                            println!(
-                                "received an awating accept ack a drone with no requested mission"
-                            );
-                        }
-                    }
-                }
-                crate::mavlink::dialects::SerpeDialect::MissionFinishedAck(msg) => {
-                    commands.entity(entity).remove::<Mission>();
-                }
-                _ => {}
-            }
+impl Mission {
+    pub fn new(state: MissionState, waypoints: Vec<Coordinates>) -> Self {
+        Self {
+            state,
+            waypoints,
+            current_waypoint: 0,
         }
     }
+
+    /// The waypoint being flown to, `None` once all of them were reached.
+    pub fn target(&self) -> Option<Coordinates> {
+        self.waypoints.get(self.current_waypoint).copied()
+    }
+
+    /// Whether the drone should be moving for this mission.
+    pub fn is_flying(&self) -> bool {
+        matches!(
+            self.state,
+            MissionState::Ongoing | MissionState::ReturningToLaunch
+        )
+    }
+
+    /// Drops the remaining waypoints and flies to `launch_point` instead.
+    pub fn return_to(&mut self, launch_point: Coordinates) {
+        self.state = MissionState::ReturningToLaunch;
+        self.waypoints = vec![launch_point];
+        self.current_waypoint = 0;
+    }
 }
 
-#[derive(Resource)]
-pub struct MissionUpdateTimer {
-    last_time: Instant,
+/// Why the drone gave up on a mission, sent in `MissionAbort::reason`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MissionAbortReason {
+    LowBattery = 0,
 }
 
-impl Default for MissionUpdateTimer {
-    fn default() -> Self {
-        Self {
-            last_time: Instant::now(),
-        }
+/// Outcome of a mission upload, sent in `MissionAck::result`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MissionResult {
+    Accepted = 0,
+    /// The drone is already flying a mission.
+    Busy = 1,
+    /// The ground station announced an empty mission.
+    Invalid = 2,
+    /// The ground station stopped sending items.
+    Timeout = 3,
+    /// The drone refuses missions, see `Fault::RefuseMissions`.
+    Denied = 4,
+}
+
+/// A waypoint list being uploaded by the ground station. Like MAVLink's
+/// mission protocol the drone pulls items one by one: `MissionCount`, then a
+/// `MissionItemRequest`/`MissionItem` pair per waypoint, then `MissionAck`.
+#[derive(Debug, Component)]
+pub struct MissionUpload {
+    count: u16,
+    waypoints: Vec<Coordinates>,
+    last_request: Instant,
+    retries: u32,
+}
+
+impl MissionUpload {
+    fn next_seq(&self) -> u16 {
+        self.waypoints.len() as u16
+    }
+
+    fn request_next(&mut self, connection: &Connection) {
+        self.last_request = Instant::now();
+        let _ = connection
+            .sender
+            .try_send(SerpeDialect::MissionItemRequest(MissionItemRequest {
+                seq: self.next_seq(),
+            }));
     }
 }
 
-pub fn system_mission_update_sender(
-    mut mission_update_timer: ResMut<MissionUpdateTimer>,
-    mut connection_query: Query<(&Drone, &mut Connection, Option<&Mission>)>,
+fn send_mission_ack(connection: &Connection, result: MissionResult) {
+    let _ = connection
+        .sender
+        .try_send(SerpeDialect::MissionAck(MissionAck {
+            result: result as u8,
+        }));
+}
+
+#[allow(clippy::type_complexity)]
+pub fn system_mission_updater(
+    mut incoming_messages: EventReader<IncomingMessage>,
+    mut drones_query: Query<(
+        Entity,
+        &Drone,
+        &mut Connection,
+        Option<&mut Mission>,
+        Option<&mut MissionUpload>,
+        Option<&Faults>,
+    )>,
+    mut commands: Commands,
 ) {
-    let current_time = Instant::now();
+    for IncomingMessage { entity, message } in incoming_messages.read() {
+        let Ok((entity, drone, connection, mut mission_opt, upload_opt, faults)) =
+            drones_query.get_mut(*entity)
+        else {
+            continue;
+        };
+        let refusing = faults.is_some_and(|faults| faults.is_active(Fault::RefuseMissions));
 
-    if current_time.duration_since(mission_update_timer.last_time) >= Duration::from_secs(1) {
-        for (drone, connection, mission_opt) in connection_query.iter_mut() {
-            match mission_opt {
-                Some(mission) => {
-                    if mission.state != MissionState::Ongoing {
-                        continue;
-                    }
+        match message {
+            SerpeDialect::MissionRequest(msg) => {
+                if refusing {
+                    // The old protocol has no way to say no, so stay silent
+                    println!("Drone {} ignored a mission request", drone.agent_id);
+                    continue;
                 }
-                None => continue,
-            }
+                if mission_opt.is_some() {
+                    // ignore if it already has a mission;
+                    continue;
+                } else {
+                    let _ = connection
+                        .sender
+                        .try_send(SerpeDialect::MissionAccept(MissionAccept {}));
 
-            let _ =
-                connection
-                    .sender
-                    .try_send(crate::mavlink::dialects::SerpeDialect::MissionUpdate(
-                        MissionUpdate {
-                            current_latitude: drone.coordinates.latitude * COORDS_ZOOM,
-                            current_longitude: drone.coordinates.longitude * COORDS_ZOOM,
-                        },
+                    commands.entity(entity).insert(Mission::new(
+                        MissionState::AwaitingAcceptAck,
+                        vec![wire::mission_target(msg)],
                     ));
-        }
+                }
+            }
+            SerpeDialect::MissionCount(msg) => {
+                if refusing {
+                    send_mission_ack(&connection, MissionResult::Denied);
+                    continue;
+                }
+                if mission_opt.is_some() {
+                    send_mission_ack(&connection, MissionResult::Busy);
+                    continue;
+                }
+                if msg.count == 0 {
+                    send_mission_ack(&connection, MissionResult::Invalid);
+                    continue;
+                }
+
+                // A new count restarts any upload already in progress
+                let mut upload = MissionUpload {
+                    count: msg.count,
+                    waypoints: Vec::with_capacity(msg.count as usize),
+                    last_request: Instant::now(),
+                    retries: 0,
+                };
+                upload.request_next(&connection);
+                commands.entity(entity).insert(upload);
+            }
+            SerpeDialect::MissionItem(msg) => {
+                let Some(mut upload) = upload_opt else {
+                    continue;
+                };
+                // Stale or duplicate items are dropped, the timeout re-requests
+                if msg.seq != upload.next_seq() {
+                    continue;
+                }
+
+                upload.waypoints.push(wire::mission_item(msg));
+                upload.retries = 0;
+
+                if upload.waypoints.len() < upload.count as usize {
+                    upload.request_next(&connection);
+                    continue;
+                }
 
-        mission_update_timer.last_time = current_time;
+                send_mission_ack(&connection, MissionResult::Accepted);
+                let waypoints = std::mem::take(&mut upload.waypoints);
+                commands
+                    .entity(entity)
+                    .remove::<MissionUpload>()
+                    .insert(Mission::new(MissionState::Ongoing, waypoints));
+            }
+            SerpeDialect::MissionAcceptAck(_) => match mission_opt {
+                Some(ref mut mission) if mission.state == MissionState::AwaitingAcceptAck => {
+                    mission.state = MissionState::Ongoing;
+                }
+                Some(_) => {
+                    println!("received an awating accept ack on already going mission");
+                }
+                None => {
+                    println!("received an awating accept ack a drone with no requested mission");
+                }
+            },
+            SerpeDialect::MissionFinishedAck(_) => {
+                commands.entity(entity).remove::<Mission>();
+            }
+            _ => {}
+        }
     }
 }
 
-pub fn system_mission_update_coordinates(
+/// Reports progress of ongoing missions at the rate set by each drone's
+/// `Telemetry`.
+#[allow(clippy::type_complexity)]
+pub fn system_mission_update_sender(
     time: Res<Time>,
-    mut connection_query: Query<(&mut Drone, &mut Mission, &mut Connection)>,
+    mut connection_query: Query<(
+        &Drone,
+        &Connection,
+        &Mission,
+        &mut Telemetry,
+        &mut DroneRng,
+        Option<&Faults>,
+    )>,
 ) {
-    for (mut drone, mut mission, connection) in connection_query.iter_mut() {
+    for (drone, connection, mission, mut telemetry, mut rng, faults) in connection_query.iter_mut()
+    {
         if mission.state != MissionState::Ongoing {
             continue;
         }
+        if !telemetry.mission_update_due(time.delta(), &mut rng.mission_update) {
+            continue;
+        }
 
-        let target = mission.target;
+        let _ = connection
+            .sender
+            .try_send(SerpeDialect::MissionUpdate(wire::mission_update(
+                &gps_position(drone, faults),
+                mission.current_waypoint,
+            )));
+    }
+}
 
-        let step = DRONE_SPEED * time.delta_seconds();
+/// Moves every drone through its flight phases: take off when a mission
+/// starts, cruise through the waypoints, then land on the last one. A drone
+/// that loses its mission mid-air lands where it is. Missions aborted with
+/// a return to launch are dropped silently on touchdown.
+#[allow(clippy::type_complexity)]
+pub fn system_mission_update_coordinates(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut drones_query: Query<(
+        Entity,
+        &mut Drone,
+        Option<&mut Mission>,
+        Option<&Connection>,
+        Option<&Faults>,
+    )>,
+) {
+    let delta = time.delta_seconds_f64();
 
-        let current_latitude = drone.coordinates.latitude;
-        let current_longitude = drone.coordinates.longitude;
+    for (entity, mut drone, mission_opt, connection_opt, faults) in drones_query.iter_mut() {
+        if faults.is_some_and(|faults| faults.is_active(Fault::StuckPosition)) {
+            continue;
+        }
+        let drone = &mut *drone;
+        let mission_opt = mission_opt.filter(|mission| mission.is_flying());
+        let climb = drone.climb_rate * delta;
 
-        let delta_latitude = target.latitude - current_latitude;
-        let delta_longitude = target.longitude - current_longitude;
+        match (drone.phase, mission_opt) {
+            (FlightPhase::Landed, Some(_)) => {
+                drone.phase = FlightPhase::TakingOff;
+                drone.launch_point = drone.coordinates;
+            }
+            (FlightPhase::Landed, None) => {}
+            (FlightPhase::TakingOff, Some(_)) => {
+                drone.coordinates.altitude =
+                    approach(drone.coordinates.altitude, drone.cruise_altitude, climb);
+                if drone.coordinates.altitude == drone.cruise_altitude {
+                    drone.phase = FlightPhase::Cruising;
+                }
+            }
+            (FlightPhase::Cruising, Some(mut mission)) => {
+                // Follow changes to the cruise altitude while flying
+                drone.coordinates.altitude =
+                    approach(drone.coordinates.altitude, drone.cruise_altitude, climb);
+                fly_waypoints(drone, &mut mission, drone.cruise_speed * delta);
+                if mission.target().is_none() {
+                    drone.phase = FlightPhase::Landing;
+                }
+            }
+            (FlightPhase::TakingOff | FlightPhase::Cruising, None) => {
+                drone.phase = FlightPhase::Landing;
+            }
+            (FlightPhase::Landing, mission_opt) => {
+                drone.coordinates.altitude = approach(drone.coordinates.altitude, 0.0, climb);
+                if drone.coordinates.altitude > 0.0 {
+                    continue;
+                }
+                drone.phase = FlightPhase::Landed;
 
-        if delta_latitude.abs() > step {
-            drone.coordinates.latitude += delta_latitude.signum() * step;
-        } else {
-            drone.coordinates.latitude = target.latitude;
+                let Some(mut mission) = mission_opt else {
+                    continue;
+                };
+                // A mission accepted while landing takes off again next frame
+                if mission.target().is_some() {
+                    continue;
+                }
+                if mission.state == MissionState::ReturningToLaunch {
+                    commands.entity(entity).remove::<Mission>();
+                    continue;
+                }
+                mission.state = MissionState::AwaitingFinishedAck;
+                if faults.is_some_and(|faults| faults.is_active(Fault::WithholdMissionFinished)) {
+                    continue;
+                }
+                if let Some(connection) = connection_opt {
+                    let _ = connection
+                        .sender
+                        .try_send(SerpeDialect::MissionFinished(MissionFinished {}));
+                }
+            }
         }
+    }
+}
 
-        if delta_longitude.abs() > step {
-            drone.coordinates.longitude += delta_longitude.signum() * step;
-        } else {
-            drone.coordinates.longitude = target.longitude;
+/// Moves `drone` up to `travel` metres along the mission's remaining
+/// waypoints. Whatever is left after reaching a waypoint carries over to the
+/// next one.
+fn fly_waypoints(drone: &mut Drone, mission: &mut Mission, mut travel: f64) {
+    while let Some(target) = mission.target() {
+        let remaining = drone.coordinates.distance_to(&target);
+        if remaining > travel {
+            let bearing = drone.coordinates.bearing_to(&target);
+            drone.coordinates = drone.coordinates.destination(bearing, travel);
+            return;
         }
 
-        if (drone.coordinates.latitude - target.latitude).abs() < step
-            && (drone.coordinates.longitude - target.longitude).abs() < step
+        drone.coordinates.latitude = target.latitude;
+        drone.coordinates.longitude = target.longitude;
+        travel -= remaining;
+        mission.current_waypoint += 1;
+    }
+}
+
+/// Steps `current` towards `target` by at most `step`.
+fn approach(current: f64, target: f64, step: f64) -> f64 {
+    if (target - current).abs() <= step {
+        target
+    } else {
+        current + step.copysign(target - current)
+    }
+}
+
+/// Sends the `MissionFinished` again on a new link, for drones that landed
+/// while theirs was down. Until it is acked the mission stays, and the drone
+/// turns down new ones.
+pub fn system_resend_mission_finished(
+    drones_query: Query<(&Mission, &Connection, Option<&Faults>), Added<Connection>>,
+) {
+    for (mission, connection, faults) in drones_query.iter() {
+        if mission.state != MissionState::AwaitingFinishedAck
+            || faults.is_some_and(|faults| faults.is_active(Fault::WithholdMissionFinished))
         {
-            mission.state = MissionState::AwaitingFinishedAck;
-            let _ = connection.sender.try_send(SerpeDialect::MissionFinished(
-                crate::mavlink::dialects::serpe_dialect::messages::MissionFinished {},
-            ));
+            continue;
         }
+        let _ = connection
+            .sender
+            .try_send(SerpeDialect::MissionFinished(MissionFinished {}));
+    }
+}
+
+/// Re-requests mission items the ground station did not answer, and gives up
+/// on the upload after `MAX_UPLOAD_RETRIES`.
+pub fn system_mission_upload_timeout(
+    mut commands: Commands,
+    mut upload_query: Query<(Entity, &Drone, &Connection, &mut MissionUpload)>,
+) {
+    for (entity, drone, connection, mut upload) in upload_query.iter_mut() {
+        if upload.last_request.elapsed() < UPLOAD_ITEM_TIMEOUT {
+            continue;
+        }
+
+        if upload.retries >= MAX_UPLOAD_RETRIES {
+            println!(
+                "Drone {} gave up on mission upload at item {}/{}",
+                drone.agent_id,
+                upload.next_seq(),
+                upload.count
+            );
+            send_mission_ack(connection, MissionResult::Timeout);
+            commands.entity(entity).remove::<MissionUpload>();
+            continue;
+        }
+
+        upload.retries += 1;
+        upload.request_next(connection);
     }
 }
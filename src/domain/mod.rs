@@ -1,4 +1,9 @@
+pub mod actions;
+pub mod battery;
 pub mod connection;
 pub mod coordinates;
 pub mod drone;
+pub mod faults;
+pub mod formation;
 pub mod mission;
+pub mod telemetry;
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use super::drone::Drone;
+
+/// Shortest interval a timer is rearmed with, however much jitter is asked.
+const MIN_INTERVAL: f64 = 0.01;
+
+/// How often a drone reports to its ground station. Intervals are in
+/// simulated seconds, each one is moved by up to `jitter` seconds either way.
+#[derive(Clone, Debug, Component)]
+pub struct Telemetry {
+    pub heartbeat_interval: f64,
+    pub mission_update_interval: f64,
+    pub jitter: f64,
+    heartbeat_timer: Timer,
+    mission_update_timer: Timer,
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Self::new(1.0, 1.0, 0.0)
+    }
+}
+
+impl Telemetry {
+    pub fn new(heartbeat_interval: f64, mission_update_interval: f64, jitter: f64) -> Self {
+        Self {
+            heartbeat_interval,
+            mission_update_interval,
+            jitter,
+            heartbeat_timer: repeating(heartbeat_interval),
+            mission_update_timer: repeating(mission_update_interval),
+        }
+    }
+
+    /// Makes edited intervals take effect now instead of after the next
+    /// message.
+    pub fn apply_intervals(&mut self) {
+        self.heartbeat_timer
+            .set_duration(interval(self.heartbeat_interval));
+        self.mission_update_timer
+            .set_duration(interval(self.mission_update_interval));
+    }
+
+    pub fn heartbeat_due(&mut self, delta: Duration, rng: &mut impl Rng) -> bool {
+        due(
+            &mut self.heartbeat_timer,
+            self.heartbeat_interval,
+            self.jitter,
+            delta,
+            rng,
+        )
+    }
+
+    pub fn mission_update_due(&mut self, delta: Duration, rng: &mut impl Rng) -> bool {
+        due(
+            &mut self.mission_update_timer,
+            self.mission_update_interval,
+            self.jitter,
+            delta,
+            rng,
+        )
+    }
+}
+
+fn interval(seconds: f64) -> Duration {
+    Duration::from_secs_f64(seconds.max(MIN_INTERVAL))
+}
+
+fn repeating(seconds: f64) -> Timer {
+    Timer::new(interval(seconds), TimerMode::Repeating)
+}
+
+/// Ticks `timer` and, when it fires, rearms it with a freshly jittered
+/// interval. A repeating timer keeps the overshoot, so the average rate
+/// stays at `seconds` whatever the tick rate.
+fn due(timer: &mut Timer, seconds: f64, jitter: f64, delta: Duration, rng: &mut impl Rng) -> bool {
+    if !timer.tick(delta).just_finished() {
+        return false;
+    }
+
+    let offset = if jitter > 0.0 {
+        rng.gen_range(-jitter..=jitter)
+    } else {
+        0.0
+    };
+    timer.set_duration(interval(seconds + offset));
+    true
+}
+
+pub fn system_attach_telemetry(
+    mut commands: Commands,
+    new_drones_query: Query<Entity, (Added<Drone>, Without<Telemetry>)>,
+) {
+    for entity in new_drones_query.iter() {
+        commands.entity(entity).insert(Telemetry::default());
+    }
+}
@@ -0,0 +1,580 @@
+//! A minimal ground station speaking the Serpe dialect, enough to run the
+//! simulator without the real one. Drones are given system ids in the order
+//! they register. Missions are sent on a timer, on demand through
+//! `Command`s, or both. Each drone is answered in the MAVLink version it
+//! registered with. Used by the `mock_ground_station` binary.
+
+use std::{
+    collections::HashMap,
+    f64::consts::TAU,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use mavio::{AsyncReceiver, AsyncSender, Message};
+use rand::Rng;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    select,
+    sync::mpsc,
+    time::{interval, MissedTickBehavior},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    domain::coordinates::Coordinates,
+    io::{
+        protocol::{FrameVerifier, Framer, ProtocolConfig, SigningConfig},
+        wire::{decode_degrees, encode_degrees},
+    },
+    mavlink::dialects::{
+        serpe_dialect::messages::{
+            HeartbeatAck, MissionAcceptAck, MissionCount, MissionFinishedAck, MissionItem,
+            MissionRequest, RegisterAck, UnregisterAck,
+        },
+        SerpeDialect,
+    },
+};
+
+/// The ground station's own id in the frames it sends, like QGroundControl's.
+pub const GROUND_STATION_SYSTEM_ID: u8 = 255;
+
+/// How long a `MissionRequest` may go unanswered before the drone is
+/// considered idle again.
+const MISSION_ACCEPT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Wait between attempts to reach a drone that listens.
+const REDIAL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Something typed on stdin, or sent by a test.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    List,
+    /// Sends a single-waypoint `MissionRequest`.
+    Mission {
+        system_id: u8,
+        target: Coordinates,
+    },
+    /// Uploads a waypoint list with `MissionCount`/`MissionItem`.
+    Upload {
+        system_id: u8,
+        waypoints: Vec<Coordinates>,
+    },
+}
+
+pub const COMMAND_HELP: &str = "\
+commands:
+  list                                   show the registered drones
+  mission <system_id> <lat> <lon>        send a MissionRequest
+  upload <system_id> <lat> <lon> [...]   upload a waypoint list";
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_whitespace();
+        let command = words.next().ok_or("empty command")?;
+        let args: Vec<&str> = words.collect();
+
+        match command {
+            "list" => Ok(Command::List),
+            "mission" | "upload" => {
+                let (system_id, coordinates) = args.split_first().ok_or("missing system id")?;
+                let system_id = system_id
+                    .parse()
+                    .map_err(|_| format!("`{}` is not a system id", system_id))?;
+                let waypoints = parse_waypoints(coordinates)?;
+
+                if command == "upload" {
+                    return Ok(Command::Upload {
+                        system_id,
+                        waypoints,
+                    });
+                }
+                match waypoints[..] {
+                    [target] => Ok(Command::Mission { system_id, target }),
+                    _ => Err("mission takes a single waypoint, use upload".to_string()),
+                }
+            }
+            other => Err(format!("unknown command `{}`", other)),
+        }
+    }
+}
+
+fn parse_waypoints(args: &[&str]) -> Result<Vec<Coordinates>, String> {
+    if args.is_empty() || args.len() % 2 == 1 {
+        return Err("expected latitude and longitude pairs".to_string());
+    }
+
+    args.chunks(2)
+        .map(|pair| {
+            let parse = |value: &str| {
+                value
+                    .parse::<f64>()
+                    .map_err(|_| format!("`{}` is not a number", value))
+            };
+            Ok(Coordinates {
+                latitude: parse(pair[0])?,
+                longitude: parse(pair[1])?,
+                altitude: 0.0,
+            })
+        })
+        .collect()
+}
+
+/// Where a drone is in the mission cycle, as far as the ground station knows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MissionStatus {
+    Idle,
+    /// Sent at this instant, waiting for `MissionAccept`.
+    Requested(Instant),
+    /// Uploading, or flying an accepted mission.
+    Busy,
+}
+
+struct DroneLink {
+    agent_id: u32,
+    sender: mpsc::Sender<SerpeDialect>,
+    position: Coordinates,
+    mission: MissionStatus,
+    /// Waypoints of the upload in progress, served on `MissionItemRequest`.
+    upload: Vec<Coordinates>,
+}
+
+impl DroneLink {
+    fn send(&self, message: SerpeDialect) {
+        let _ = self.sender.try_send(message);
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    next_system_id: u8,
+    drones: HashMap<u8, DroneLink>,
+}
+
+impl Registry {
+    /// Hands out ids 1 to 254, skipping the ones in use. `None` when full.
+    fn allocate(&mut self) -> Option<u8> {
+        for _ in 0..u8::MAX {
+            self.next_system_id = self.next_system_id % (GROUND_STATION_SYSTEM_ID - 1) + 1;
+            if !self.drones.contains_key(&self.next_system_id) {
+                return Some(self.next_system_id);
+            }
+        }
+        None
+    }
+}
+
+type SharedRegistry = Arc<Mutex<Registry>>;
+
+/// How the ground station behaves, see the `mock_ground_station` flags.
+#[derive(Clone, Debug)]
+pub struct GroundStationOptions {
+    /// Sends a mission to every idle drone this often.
+    pub mission_interval: Option<Duration>,
+    /// How far from the drone scheduled missions go, in metres.
+    pub mission_distance: f64,
+    /// Also print heartbeats and mission updates.
+    pub verbose: bool,
+    /// Drones listening on these addresses are dialled, besides the ones
+    /// connecting to the listener.
+    pub connect: Vec<String>,
+    /// Signs every frame and turns down unsigned, forged and replayed ones.
+    /// Drones speaking MAVLink 1 are turned away.
+    pub signing: Option<SigningConfig>,
+}
+
+impl Default for GroundStationOptions {
+    fn default() -> Self {
+        Self {
+            mission_interval: None,
+            mission_distance: 500.0,
+            verbose: false,
+            connect: Vec::new(),
+            signing: None,
+        }
+    }
+}
+
+/// Serves drones connecting to `listener`, and dials the ones in
+/// `options.connect`, until `token` is cancelled.
+pub async fn run(
+    listener: TcpListener,
+    mut commands: mpsc::Receiver<Command>,
+    options: GroundStationOptions,
+    token: CancellationToken,
+) {
+    let registry = SharedRegistry::default();
+    // Only ticked when an interval is set, the fallback doesn't matter
+    let mut missions = interval(options.mission_interval.unwrap_or(Duration::from_secs(60)));
+    missions.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    // The first tick fires at once, nobody is registered yet anyway
+    missions.tick().await;
+
+    for address in &options.connect {
+        tokio::spawn(dial_drone(
+            address.clone(),
+            registry.clone(),
+            options.verbose,
+            options.signing.clone(),
+            token.child_token(),
+        ));
+    }
+
+    loop {
+        select! {
+            _ = token.cancelled() => break,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_drone(
+                        stream,
+                        registry.clone(),
+                        options.verbose,
+                        options.signing.clone(),
+                        token.child_token(),
+                    ));
+                }
+                Err(err) => println!("Failed to accept a connection: {}", err),
+            },
+            Some(command) = commands.recv() => execute(&registry, command),
+            _ = missions.tick(), if options.mission_interval.is_some() => {
+                dispatch_missions(&registry, options.mission_distance);
+            },
+        }
+    }
+}
+
+fn execute(registry: &SharedRegistry, command: Command) {
+    let mut registry = registry.lock().unwrap();
+
+    let system_id = match &command {
+        Command::List => {
+            if registry.drones.is_empty() {
+                println!("No drones registered");
+            }
+            let mut ids: Vec<_> = registry.drones.keys().copied().collect();
+            ids.sort();
+            for system_id in ids {
+                let drone = &registry.drones[&system_id];
+                println!(
+                    "System {}: agent {} at {:.6}, {:.6} ({:?})",
+                    system_id,
+                    drone.agent_id,
+                    drone.position.latitude,
+                    drone.position.longitude,
+                    drone.mission
+                );
+            }
+            return;
+        }
+        Command::Mission { system_id, .. } | Command::Upload { system_id, .. } => *system_id,
+    };
+
+    let Some(drone) = registry.drones.get_mut(&system_id) else {
+        println!("No drone with system id {}", system_id);
+        return;
+    };
+
+    match command {
+        Command::List => {}
+        Command::Mission { target, .. } => request_mission(system_id, drone, &target),
+        Command::Upload { waypoints, .. } => {
+            let Ok(count) = u16::try_from(waypoints.len()) else {
+                println!("Too many waypoints");
+                return;
+            };
+            println!("Uploading {} waypoints to system {}", count, system_id);
+            drone.upload = waypoints;
+            drone.mission = MissionStatus::Busy;
+            drone.send(SerpeDialect::MissionCount(MissionCount { count }));
+        }
+    }
+}
+
+fn request_mission(system_id: u8, drone: &mut DroneLink, target: &Coordinates) {
+    println!(
+        "Sending system {} to {:.6}, {:.6}",
+        system_id, target.latitude, target.longitude
+    );
+    drone.mission = MissionStatus::Requested(Instant::now());
+    drone.send(SerpeDialect::MissionRequest(MissionRequest {
+        target_latitude: encode_degrees(target.latitude),
+        target_longitude: encode_degrees(target.longitude),
+    }));
+}
+
+/// Sends every idle drone somewhere `distance` metres away, in a random
+/// direction.
+fn dispatch_missions(registry: &SharedRegistry, distance: f64) {
+    let mut registry = registry.lock().unwrap();
+    let mut rng = rand::thread_rng();
+
+    for (system_id, drone) in registry.drones.iter_mut() {
+        if let MissionStatus::Requested(sent_at) = drone.mission {
+            if sent_at.elapsed() < MISSION_ACCEPT_TIMEOUT {
+                continue;
+            }
+            println!("System {} never accepted its mission", system_id);
+            drone.mission = MissionStatus::Idle;
+        }
+        if drone.mission != MissionStatus::Idle {
+            continue;
+        }
+
+        let target = drone
+            .position
+            .destination(rng.gen_range(0.0..TAU), distance);
+        request_mission(*system_id, drone, &target);
+    }
+}
+
+/// Keeps a link to the drone listening on `address`, dialling again whenever
+/// it drops.
+async fn dial_drone(
+    address: String,
+    registry: SharedRegistry,
+    verbose: bool,
+    signing: Option<SigningConfig>,
+    token: CancellationToken,
+) {
+    loop {
+        if let Ok(stream) = TcpStream::connect(&address).await {
+            println!("Connected to {}", address);
+            handle_drone(
+                stream,
+                registry.clone(),
+                verbose,
+                signing.clone(),
+                token.clone(),
+            )
+            .await;
+        }
+
+        select! {
+            _ = token.cancelled() => return,
+            _ = tokio::time::sleep(REDIAL_INTERVAL) => {},
+        }
+    }
+}
+
+async fn handle_drone(
+    stream: TcpStream,
+    registry: SharedRegistry,
+    verbose: bool,
+    signing: Option<SigningConfig>,
+    token: CancellationToken,
+) {
+    let (reader, writer) = stream.into_split();
+    let mut receiver = AsyncReceiver::versionless(reader);
+    let mut sender = AsyncSender::versionless(writer);
+
+    let Ok(first_frame) = receiver.recv().await else {
+        println!("Dropped a connection that sent no frame");
+        return;
+    };
+    // Whatever version the drone registers with is the link's from now on
+    let protocol = ProtocolConfig {
+        version: first_frame.version().into(),
+        signing,
+    };
+    let (mut framer, mut verifier) = match Framer::new(GROUND_STATION_SYSTEM_ID, &protocol)
+        .and_then(|framer| Ok((framer, FrameVerifier::new(&protocol)?)))
+    {
+        Ok(framing) => framing,
+        Err(err) => {
+            println!("Dropped a {} connection: {}", protocol.version, err);
+            return;
+        }
+    };
+
+    // Drones have to register before anything else
+    if let Err(rejection) = verifier.check(&first_frame) {
+        println!(
+            "Dropped a connection, its Register was rejected: {}",
+            rejection
+        );
+        return;
+    }
+    let register = match first_frame.decode() {
+        Ok(SerpeDialect::Register(register)) => register,
+        _ => {
+            println!("Dropped a connection that didn't start with Register");
+            return;
+        }
+    };
+    let position = Coordinates {
+        latitude: decode_degrees(register.latitude),
+        longitude: decode_degrees(register.longitude),
+        altitude: 0.0,
+    };
+
+    let (outgoing_sender, mut outgoing_receiver) = mpsc::channel(64);
+    let Some(system_id) = ({
+        let mut registry = registry.lock().unwrap();
+        let system_id = registry.allocate();
+        if let Some(system_id) = system_id {
+            registry.drones.insert(
+                system_id,
+                DroneLink {
+                    agent_id: register.agent_id,
+                    sender: outgoing_sender.clone(),
+                    position,
+                    mission: MissionStatus::Idle,
+                    upload: Vec::new(),
+                },
+            );
+        }
+        system_id
+    }) else {
+        println!("No system id left for agent {}", register.agent_id);
+        return;
+    };
+    println!(
+        "Agent {} registered as system {}",
+        register.agent_id, system_id
+    );
+    let _ = outgoing_sender.try_send(SerpeDialect::RegisterAck(RegisterAck { system_id }));
+
+    let writer_token = token.clone();
+    let writer = tokio::spawn(async move {
+        loop {
+            let message = select! {
+                _ = writer_token.cancelled() => break,
+                message = outgoing_receiver.recv() => message,
+            };
+            let Some(message) = message else {
+                break;
+            };
+            let message: &dyn Message = match &message {
+                SerpeDialect::RegisterAck(msg) => msg,
+                SerpeDialect::UnregisterAck(msg) => msg,
+                SerpeDialect::HeartbeatAck(msg) => msg,
+                SerpeDialect::MissionRequest(msg) => msg,
+                SerpeDialect::MissionAcceptAck(msg) => msg,
+                SerpeDialect::MissionFinishedAck(msg) => msg,
+                SerpeDialect::MissionCount(msg) => msg,
+                SerpeDialect::MissionItem(msg) => msg,
+                _ => continue,
+            };
+            let Ok(frame) = framer.next_frame(message) else {
+                continue;
+            };
+            if sender.send(&frame).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let reason = loop {
+        let frame = select! {
+            _ = token.cancelled() => break "ground station shutting down",
+            frame = receiver.recv() => frame,
+        };
+        let Ok(frame) = frame else {
+            break "connection lost";
+        };
+        if let Err(rejection) = verifier.check(&frame) {
+            println!("System {}: rejected a frame: {}", system_id, rejection);
+            continue;
+        }
+        let Ok(message) = frame.decode::<SerpeDialect>() else {
+            continue;
+        };
+
+        let mut registry = registry.lock().unwrap();
+        let Some(drone) = registry.drones.get_mut(&system_id) else {
+            break "unknown system";
+        };
+        if handle_message(system_id, drone, message, verbose) {
+            break "unregistered";
+        }
+    };
+
+    registry.lock().unwrap().drones.remove(&system_id);
+    println!("System {} left: {}", system_id, reason);
+
+    // Let the UnregisterAck go out before the socket closes
+    drop(outgoing_sender);
+    let _ = writer.await;
+}
+
+/// Answers one message from `drone`. Returns true once it unregistered.
+fn handle_message(
+    system_id: u8,
+    drone: &mut DroneLink,
+    message: SerpeDialect,
+    verbose: bool,
+) -> bool {
+    match message {
+        SerpeDialect::Heartbeat(msg) => {
+            drone.position.latitude = decode_degrees(msg.latitude);
+            drone.position.longitude = decode_degrees(msg.longitude);
+            if verbose {
+                println!(
+                    "System {} heartbeat at {:.6}, {:.6}, battery {}%",
+                    system_id,
+                    drone.position.latitude,
+                    drone.position.longitude,
+                    msg.battery_remaining
+                );
+            }
+            drone.send(SerpeDialect::HeartbeatAck(HeartbeatAck {}));
+        }
+        SerpeDialect::MissionAccept(_) => {
+            println!("System {} accepted its mission", system_id);
+            drone.mission = MissionStatus::Busy;
+            drone.send(SerpeDialect::MissionAcceptAck(MissionAcceptAck {}));
+        }
+        SerpeDialect::MissionUpdate(msg) => {
+            drone.position.latitude = decode_degrees(msg.current_latitude);
+            drone.position.longitude = decode_degrees(msg.current_longitude);
+            if verbose {
+                println!(
+                    "System {} at waypoint {}, {:.6}, {:.6}",
+                    system_id,
+                    msg.current_waypoint,
+                    drone.position.latitude,
+                    drone.position.longitude
+                );
+            }
+        }
+        SerpeDialect::MissionFinished(_) => {
+            println!("System {} finished its mission", system_id);
+            drone.mission = MissionStatus::Idle;
+            drone.send(SerpeDialect::MissionFinishedAck(MissionFinishedAck {}));
+        }
+        SerpeDialect::MissionItemRequest(msg) => match drone.upload.get(msg.seq as usize) {
+            Some(waypoint) => drone.send(SerpeDialect::MissionItem(MissionItem {
+                seq: msg.seq,
+                latitude: encode_degrees(waypoint.latitude),
+                longitude: encode_degrees(waypoint.longitude),
+            })),
+            None => println!("System {} asked for unknown item {}", system_id, msg.seq),
+        },
+        SerpeDialect::MissionAck(msg) => {
+            println!(
+                "System {} answered the upload with {}",
+                system_id, msg.result
+            );
+            drone.upload.clear();
+            // 0 is accepted, anything else means the drone isn't flying it
+            if msg.result != 0 {
+                drone.mission = MissionStatus::Idle;
+            }
+        }
+        SerpeDialect::MissionAbort(msg) => {
+            println!(
+                "System {} aborted its mission (reason {})",
+                system_id, msg.reason
+            );
+            drone.mission = MissionStatus::Idle;
+        }
+        SerpeDialect::Unregister(_) => {
+            drone.send(SerpeDialect::UnregisterAck(UnregisterAck {}));
+            return true;
+        }
+        _ => {}
+    }
+    false
+}
@@ -0,0 +1,63 @@
+use core::fmt;
+use std::sync::Arc;
+
+use super::protocol::Rejection;
+
+/// Everything that can go wrong between the simulator and a ground station.
+#[derive(Clone, Debug)]
+pub enum IoError {
+    /// The link to the ground station could not be opened.
+    Connect {
+        address: String,
+        source: Arc<std::io::Error>,
+    },
+    /// A message could not be turned into a MAVLink frame.
+    Encode(mavio::error::Error),
+    /// A frame could not be read from or decoded into the dialect.
+    Decode(mavio::error::Error),
+    /// Writing a frame to the socket failed.
+    Send(mavio::error::Error),
+    /// The ground station answered with something other than what we expected.
+    UnexpectedMessage {
+        expected: &'static str,
+        message_id: u32,
+    },
+    /// The drone's `ProtocolConfig` can't be used, e.g. a malformed key.
+    Protocol(String),
+    /// The ground station's answer failed the link's version or signing
+    /// checks.
+    Rejected(Rejection),
+    /// Waited too long for the ground station.
+    Timeout { waiting_for: &'static str },
+    /// The other side of an internal channel went away.
+    ChannelClosed,
+}
+
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IoError::Connect { address, source } => {
+                write!(f, "could not connect to {}: {}", address, source)
+            }
+            IoError::Encode(err) => write!(f, "could not encode message: {}", err),
+            IoError::Decode(err) => write!(f, "could not decode message: {}", err),
+            IoError::Send(err) => write!(f, "could not send message: {}", err),
+            IoError::UnexpectedMessage {
+                expected,
+                message_id,
+            } => write!(
+                f,
+                "expected {}, received message with id {}",
+                expected, message_id
+            ),
+            IoError::Protocol(err) => write!(f, "invalid protocol settings: {}", err),
+            IoError::Rejected(rejection) => {
+                write!(f, "rejected frame from the ground station: {}", rejection)
+            }
+            IoError::Timeout { waiting_for } => write!(f, "timed out waiting for {}", waiting_for),
+            IoError::ChannelClosed => write!(f, "channel closed"),
+        }
+    }
+}
+
+impl std::error::Error for IoError {}
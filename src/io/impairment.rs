@@ -0,0 +1,306 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use mavio::{prelude::Versionless, Frame};
+use rand::Rng;
+use rand_chacha::{rand_core::SeedableRng, ChaCha8Rng};
+use serde::Deserialize;
+use tokio::sync::watch;
+
+/// Upper bound for the UI, a link slower than this is as good as down.
+pub const MAX_LATENCY_MS: f64 = 10_000.0;
+
+/// Lower bound for the bandwidth, in bytes per second. Anything slower keeps
+/// a single frame on the link for minutes.
+pub const MIN_BANDWIDTH: f64 = 1.0;
+
+/// A flaky radio link between a drone and its ground station. Applied to
+/// every frame on both directions of the connection, in real time since the
+/// ground station doesn't know about the simulation clock.
+#[derive(Clone, Debug, Default, PartialEq, Component, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Impairment {
+    /// Added to every frame, in milliseconds.
+    pub latency: f64,
+    /// Up to this many extra milliseconds, drawn per frame.
+    pub jitter: f64,
+    /// Probability of a frame being lost, between 0 and 1.
+    pub drop_rate: f64,
+    /// Probability of a frame arriving twice, between 0 and 1.
+    pub duplicate_rate: f64,
+    /// Bytes per second, unlimited when `None`.
+    pub bandwidth: Option<f64>,
+}
+
+impl Impairment {
+    pub fn is_none(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=MAX_LATENCY_MS).contains(&self.latency) {
+            return Err(format!("latency must be within 0..={MAX_LATENCY_MS} ms"));
+        }
+        if !(0.0..=MAX_LATENCY_MS).contains(&self.jitter) {
+            return Err(format!("jitter must be within 0..={MAX_LATENCY_MS} ms"));
+        }
+        if !(0.0..=1.0).contains(&self.drop_rate) {
+            return Err("drop_rate must be within 0..=1".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.duplicate_rate) {
+            return Err("duplicate_rate must be within 0..=1".to_string());
+        }
+        if self
+            .bandwidth
+            .is_some_and(|bandwidth| !bandwidth.is_finite() || bandwidth < MIN_BANDWIDTH)
+        {
+            return Err(format!("bandwidth must be at least {MIN_BANDWIDTH} B/s"));
+        }
+        Ok(())
+    }
+}
+
+/// The draws for one direction of a drone's link, taken from the simulation
+/// seed so an impaired run repeats with `--seed`.
+pub fn link_rng(seed: u64, agent_id: u32, incoming: bool) -> ChaCha8Rng {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    // Every drone and direction gets a stream of its own
+    rng.set_stream(((agent_id as u64) << 1) | incoming as u64);
+    rng
+}
+
+pub type ImpairmentSender = watch::Sender<Impairment>;
+pub type ImpairmentReceiver = watch::Receiver<Impairment>;
+
+/// Holds frames back until the current `Impairment` lets them through.
+/// Frames can be lost or repeated but never overtake each other, whatever
+/// the transport: jitter only ever delays the rest of the queue.
+pub struct ImpairedQueue {
+    impairment: ImpairmentReceiver,
+    frames: VecDeque<(Instant, Frame<Versionless>)>,
+    /// When the last queued frame finishes going through the bandwidth limit.
+    link_free_at: Instant,
+    rng: ChaCha8Rng,
+}
+
+impl ImpairedQueue {
+    /// `rng` decides which frames are dropped, repeated or delayed, see
+    /// `link_rng`.
+    pub fn new(impairment: ImpairmentReceiver, rng: ChaCha8Rng) -> Self {
+        Self {
+            impairment,
+            frames: VecDeque::new(),
+            link_free_at: Instant::now(),
+            rng,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn push(&mut self, frame: Frame<Versionless>) {
+        let impairment = self.impairment.borrow().clone();
+        if self.rng.gen_bool(impairment.drop_rate.clamp(0.0, 1.0)) {
+            return;
+        }
+        let copies = if self.rng.gen_bool(impairment.duplicate_rate.clamp(0.0, 1.0)) {
+            2
+        } else {
+            1
+        };
+
+        let size = frame.header().size() + frame.body_length();
+        for _ in 0..copies {
+            let now = Instant::now();
+            let mut departure = self.link_free_at.max(now);
+            if let Some(bandwidth) = impairment
+                .bandwidth
+                .filter(|bandwidth| *bandwidth >= MIN_BANDWIDTH)
+            {
+                departure += Duration::from_secs_f64(size as f64 / bandwidth);
+            }
+            self.link_free_at = departure;
+
+            let mut delay = impairment.latency.max(0.0);
+            if impairment.jitter > 0.0 {
+                delay += self.rng.gen_range(0.0..impairment.jitter);
+            }
+            let mut deliver_at = departure + Duration::from_secs_f64(delay / 1000.0);
+            if let Some((last, _)) = self.frames.back() {
+                deliver_at = deliver_at.max(*last);
+            }
+
+            self.frames.push_back((deliver_at, frame.clone()));
+        }
+    }
+
+    /// Waits for the oldest frame to be due. Never resolves while the queue
+    /// is empty, and loses nothing when cancelled.
+    pub async fn next_due(&mut self) -> Frame<Versionless> {
+        let Some((deliver_at, _)) = self.frames.front() else {
+            return std::future::pending().await;
+        };
+        tokio::time::sleep_until((*deliver_at).into()).await;
+
+        let (_, frame) = self.frames.pop_front().unwrap();
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::coordinates::DEFAULT_COORDINATES,
+        io::{
+            protocol::{Framer, ProtocolConfig},
+            wire,
+        },
+    };
+
+    fn queue(impairment: Impairment) -> ImpairedQueue {
+        let (_, receiver) = watch::channel(impairment);
+        ImpairedQueue::new(receiver, link_rng(42, 1, false))
+    }
+
+    fn frame() -> Frame<Versionless> {
+        let mut framer = Framer::new(1, &ProtocolConfig::default()).unwrap();
+        framer
+            .next_frame(&wire::heartbeat(&DEFAULT_COORDINATES, None))
+            .unwrap()
+    }
+
+    fn deliveries(queue: &ImpairedQueue) -> Vec<Instant> {
+        queue
+            .frames
+            .iter()
+            .map(|(deliver_at, _)| *deliver_at)
+            .collect()
+    }
+
+    #[test]
+    fn rejects_values_that_are_not_finite() {
+        for impairment in [
+            Impairment {
+                latency: f64::NAN,
+                ..default()
+            },
+            Impairment {
+                latency: f64::INFINITY,
+                ..default()
+            },
+            Impairment {
+                jitter: f64::NAN,
+                ..default()
+            },
+            Impairment {
+                drop_rate: f64::NAN,
+                ..default()
+            },
+            Impairment {
+                duplicate_rate: f64::INFINITY,
+                ..default()
+            },
+            Impairment {
+                bandwidth: Some(f64::NAN),
+                ..default()
+            },
+            Impairment {
+                bandwidth: Some(f64::INFINITY),
+                ..default()
+            },
+            Impairment {
+                bandwidth: Some(1e-300),
+                ..default()
+            },
+            Impairment {
+                bandwidth: Some(0.0),
+                ..default()
+            },
+        ] {
+            assert!(
+                impairment.validate().is_err(),
+                "{:?} was accepted",
+                impairment
+            );
+        }
+
+        let slowest = Impairment {
+            bandwidth: Some(MIN_BANDWIDTH),
+            ..default()
+        };
+        assert_eq!(slowest.validate(), Ok(()));
+    }
+
+    #[test]
+    fn drops_every_frame_at_full_loss() {
+        let mut queue = queue(Impairment {
+            drop_rate: 1.0,
+            ..default()
+        });
+        for _ in 0..20 {
+            queue.push(frame());
+        }
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn repeats_every_frame_at_full_duplication() {
+        let mut queue = queue(Impairment {
+            duplicate_rate: 1.0,
+            ..default()
+        });
+        for _ in 0..10 {
+            queue.push(frame());
+        }
+        assert_eq!(queue.frames.len(), 20);
+    }
+
+    #[test]
+    fn jitter_never_reorders_frames() {
+        let mut queue = queue(Impairment {
+            latency: 100.0,
+            jitter: 50.0,
+            ..default()
+        });
+        let start = Instant::now();
+        for _ in 0..50 {
+            queue.push(frame());
+        }
+        let end = Instant::now();
+
+        let deliveries = deliveries(&queue);
+        assert_eq!(deliveries.len(), 50);
+        assert!(deliveries.windows(2).all(|pair| pair[0] <= pair[1]));
+        for deliver_at in deliveries {
+            assert!(deliver_at >= start + Duration::from_millis(100));
+            assert!(deliver_at <= end + Duration::from_millis(150));
+        }
+    }
+
+    #[test]
+    fn bandwidth_spaces_frames_by_their_size() {
+        let bandwidth = 1000.0;
+        let mut queue = queue(Impairment {
+            bandwidth: Some(bandwidth),
+            ..default()
+        });
+        let frame = frame();
+        let size = frame.header().size() + frame.body_length();
+        let start = Instant::now();
+        for _ in 0..5 {
+            queue.push(frame.clone());
+        }
+
+        let on_the_wire = Duration::from_secs_f64(size as f64 / bandwidth);
+        let deliveries = deliveries(&queue);
+        assert!(deliveries[0] >= start + on_the_wire);
+        for pair in deliveries.windows(2) {
+            assert_eq!(pair[1] - pair[0], on_the_wire);
+        }
+    }
+}
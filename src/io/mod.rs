@@ -1,75 +1,138 @@
+use std::time::Duration;
+
 use bevy::prelude::*;
-use mavio::{prelude::V2, AsyncReceiver, AsyncSender, Endpoint, Frame, MavLinkId};
-use tokio::{
-    net::{
-        tcp::{OwnedReadHalf, OwnedWriteHalf},
-        TcpStream,
+use mavio::{prelude::Versionless, AsyncReceiver, AsyncSender, Frame, Message};
+use tokio::{select, sync::watch, time::timeout};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+use crate::{
+    domain::{
+        connection::{Connection, LinkHealth},
+        coordinates::Coordinates,
     },
-    select,
+    mavlink::dialects::SerpeDialect,
 };
-use tokio_util::sync::CancellationToken;
 
-use crate::{
-    domain::{connection::Connection, coordinates::Coordinates},
-    mavlink::dialects::{serpe_dialect::messages::Register, SerpeDialect},
+use self::{
+    error::IoError,
+    impairment::{link_rng, ImpairedQueue, Impairment},
+    protocol::{FrameVerifier, Framer, ProtocolConfig, Tampering, TamperingReceiver},
+    transport::{shared::SharedPorts, TransportConfig, TransportReader, TransportWriter},
 };
 
+pub mod error;
+pub mod impairment;
+pub mod protocol;
+pub mod transport;
+pub mod wire;
+
 pub enum IOMessage {
     CreateConnection {
-        agent_id: u32,
-        tx: tokio::sync::oneshot::Sender<Connection>,
-        coordinates: Coordinates,
+        request: ConnectionRequest,
+        tx: ConnectionResultSender,
     },
 }
 
+/// Everything the IO runtime needs to connect one drone.
+#[derive(Clone, Debug)]
+pub struct ConnectionRequest {
+    pub agent_id: u32,
+    /// The ground station, as the dialling transports understand it.
+    pub address: String,
+    pub transport: TransportConfig,
+    pub protocol: ProtocolConfig,
+    pub handshake_timeout: Duration,
+    pub unregister_timeout: Duration,
+    pub coordinates: Coordinates,
+    /// The simulation seed, for the impairment's draws.
+    pub seed: u64,
+}
+
+pub type ConnectionResult = Result<Connection, IoError>;
+pub type ConnectionResultSender = tokio::sync::oneshot::Sender<ConnectionResult>;
+pub type ConnectionResultReceiver = tokio::sync::oneshot::Receiver<ConnectionResult>;
+
 pub type IOMessageReceiver = tokio::sync::mpsc::Receiver<IOMessage>;
 pub type IOMessageSender = tokio::sync::mpsc::Sender<IOMessage>;
 pub type SerpeDialectReceiver = tokio::sync::mpsc::Receiver<SerpeDialect>;
 pub type SerpeDialectSender = tokio::sync::mpsc::Sender<SerpeDialect>;
 
-pub type RealSender = AsyncSender<OwnedWriteHalf, V2>;
-pub type RealReceiver = AsyncReceiver<OwnedReadHalf, V2>;
+pub type RealSender = AsyncSender<TransportWriter, Versionless>;
+pub type RealReceiver = AsyncReceiver<TransportReader, Versionless>;
 
 #[derive(Resource)]
 pub struct IOResource {
     pub sender: IOMessageSender,
 }
 
+/// Asks the IO runtime to connect a drone. Returns immediately, the result
+/// arrives later on the returned receiver.
+pub fn create_connection(
+    request: ConnectionRequest,
+    io_sender: &IOResource,
+) -> Result<ConnectionResultReceiver, IoError> {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let message = IOMessage::CreateConnection { request, tx };
+
+    io_sender
+        .sender
+        .try_send(message)
+        .map_err(|_| IoError::ChannelClosed)?;
+
+    Ok(rx)
+}
+
+/// Sends the `Register` that opens every link. The drone has no system id
+/// yet, so `framer` is expected to send as system 0.
 pub async fn send_registration(
     agent_id: u32,
     real_sender: &mut RealSender,
+    framer: &mut Framer,
     coordinates: &Coordinates,
-) -> Result<(), ()> {
-    let message = Register {
-        agent_id,
-        latitude: coordinates.latitude,
-        longitude: coordinates.longitude,
-    };
-    let first_frame = Frame::builder()
-        .sequence(0)
-        .system_id(0)
-        .component_id(0)
-        .version(V2)
-        .message(&message)
-        .map_err(|_| ())?
-        .build()
-        .into();
-
-    real_sender.send(&first_frame).await.map_err(|_| ())?;
+) -> Result<(), IoError> {
+    let message = wire::register(agent_id, coordinates);
+    let first_frame = framer.next_frame(&message).map_err(IoError::Encode)?;
+
+    real_sender
+        .send(&first_frame)
+        .await
+        .map_err(IoError::Send)?;
+    real_sender.flush().await.map_err(IoError::Send)?;
     Ok(())
 }
 
-pub async fn wait_for_register_ack(real_receiver: &mut RealReceiver) -> Result<u8, ()> {
-    let first_frame = real_receiver.recv().await.map_err(|_| ())?;
+pub async fn wait_for_register_ack(
+    real_receiver: &mut RealReceiver,
+    verifier: &mut FrameVerifier,
+    ack_timeout: Duration,
+) -> Result<u8, IoError> {
+    let first_frame = timeout(ack_timeout, real_receiver.recv())
+        .await
+        .map_err(|_| IoError::Timeout {
+            waiting_for: "register ack",
+        })?
+        .map_err(IoError::Decode)?;
+    verifier.check(&first_frame).map_err(IoError::Rejected)?;
 
-    if let Ok(SerpeDialect::RegisterAck(msg)) = first_frame.decode::<SerpeDialect>() {
-        Ok(msg.system_id)
-    } else {
-        Err(())
+    match first_frame.decode::<SerpeDialect>() {
+        Ok(SerpeDialect::RegisterAck(msg)) => Ok(msg.system_id),
+        Ok(_) => Err(IoError::UnexpectedMessage {
+            expected: "register ack",
+            message_id: first_frame.message_id(),
+        }),
+        Err(err) => Err(IoError::Decode(err)),
     }
 }
 
-pub async fn run_io(mut receiver: IOMessageReceiver, token: CancellationToken) {
+/// Runs until `token` is cancelled. Every connection is spawned on `tracker`
+/// so shutdown can wait for their `Unregister` handshakes to finish.
+pub async fn run_io(
+    mut receiver: IOMessageReceiver,
+    token: CancellationToken,
+    tracker: TaskTracker,
+) {
+    let shared_ports = SharedPorts::new(token.clone());
+
     loop {
         select! {
             // Listen for the cancellation signal
@@ -80,12 +143,13 @@ pub async fn run_io(mut receiver: IOMessageReceiver, token: CancellationToken) {
             // Listen for messages from the receiver
             maybe_message = receiver.recv() => {
                 match maybe_message {
-                    Some(IOMessage::CreateConnection {
-                        agent_id,
-                        tx,
-                        coordinates,
-                    }) => {
-                        tokio::spawn(handle_new_connection(agent_id, tx, coordinates));
+                    Some(IOMessage::CreateConnection { request, tx }) => {
+                        tracker.spawn(handle_new_connection(
+                            request,
+                            tx,
+                            shared_ports.clone(),
+                            token.child_token(),
+                        ));
                     },
                     None => {
                         // If the receiver is closed, exit the loop
@@ -98,96 +162,306 @@ pub async fn run_io(mut receiver: IOMessageReceiver, token: CancellationToken) {
 }
 
 async fn handle_new_connection(
-    agent_id: u32,
-    tx: tokio::sync::oneshot::Sender<Connection>,
-    coordinates: Coordinates,
+    request: ConnectionRequest,
+    mut tx: ConnectionResultSender,
+    shared_ports: SharedPorts,
+    token: CancellationToken,
 ) {
-    if let Ok(stream) = TcpStream::connect("127.0.0.1:8000").await {
-        let (reader, writer) = stream.into_split();
+    let ConnectionRequest {
+        agent_id,
+        address,
+        transport,
+        protocol,
+        handshake_timeout,
+        unregister_timeout,
+        coordinates,
+        seed,
+    } = request;
 
-        let mut real_sender = AsyncSender::versioned(writer, V2);
-        let mut real_receiver = AsyncReceiver::versioned(reader, V2);
+    let framing = Framer::new(0, &protocol)
+        .and_then(|framer| FrameVerifier::new(&protocol).map(|verifier| (framer, verifier)));
+    let (mut framer, mut verifier) = match framing {
+        Ok(framing) => framing,
+        Err(err) => {
+            let _ = tx.send(Err(IoError::Protocol(err)));
+            return;
+        }
+    };
 
-        if send_registration(agent_id, &mut real_sender, &coordinates)
-            .await
-            .is_err()
-        {
+    let opening = transport.open(&address, &shared_ports);
+    let opened = if transport.waits_for_peer() {
+        // The ground station dials in whenever it likes, so wait for as long
+        // as the drone does
+        select! {
+            opened = opening => opened,
+            _ = tx.closed() => return,
+            _ = token.cancelled() => return,
+        }
+    } else {
+        match timeout(handshake_timeout, opening).await {
+            Ok(opened) => opened,
+            Err(_) => {
+                let _ = tx.send(Err(IoError::Timeout {
+                    waiting_for: "connection",
+                }));
+                return;
+            }
+        }
+    };
+    let (reader, writer) = match opened {
+        Ok(halves) => halves,
+        Err(source) => {
+            let _ = tx.send(Err(IoError::Connect {
+                address: transport.describe(&address),
+                source: source.into(),
+            }));
             return;
         }
+    };
+
+    let mut real_sender = AsyncSender::versionless(writer);
+    let mut real_receiver = AsyncReceiver::versionless(reader);
 
-        // Save the system_id received from the register ack
-        let system_id = match wait_for_register_ack(&mut real_receiver).await {
+    if let Err(err) = send_registration(agent_id, &mut real_sender, &mut framer, &coordinates).await
+    {
+        let _ = tx.send(Err(err));
+        return;
+    }
+
+    // Save the system_id received from the register ack
+    let system_id =
+        match wait_for_register_ack(&mut real_receiver, &mut verifier, handshake_timeout).await {
             Ok(id) => id,
-            Err(_) => return, // Handle error appropriately
+            Err(err) => {
+                let _ = tx.send(Err(err));
+                return;
+            }
         };
+    framer.set_system_id(system_id);
 
-        // Continue with your logic
-        let (incoming_sender, incoming_receiver) = tokio::sync::mpsc::channel(256);
-        let (outgoing_sender, outgoing_receiver) = tokio::sync::mpsc::channel(256);
+    let (incoming_sender, incoming_receiver) = tokio::sync::mpsc::channel(256);
+    let (outgoing_sender, outgoing_receiver) = tokio::sync::mpsc::channel(256);
+    let (error_sender, error_receiver) = tokio::sync::oneshot::channel();
+    let (impairment_sender, impairment_receiver) = watch::channel(Impairment::default());
+    let (tampering_sender, tampering_receiver) = watch::channel(Tampering::default());
 
-        tx.send(Connection {
-            system_id,
-            receiver: incoming_receiver,
-            sender: outgoing_sender,
-        })
-        .unwrap();
+    let connection = Connection {
+        system_id,
+        receiver: incoming_receiver,
+        sender: outgoing_sender,
+        error_receiver,
+        last_error: None,
+        health: LinkHealth::default(),
+        impairment: impairment_sender,
+        tampering: tampering_sender,
+    };
+    if tx.send(Ok(connection)).is_err() {
+        // Nobody is waiting for this connection anymore (e.g. drone deleted)
+        return;
+    }
+
+    let mut write_handle = tokio::spawn(write(
+        outgoing_receiver,
+        real_sender,
+        framer,
+        unregister_timeout,
+        ImpairedQueue::new(impairment_receiver.clone(), link_rng(seed, agent_id, false)),
+        tampering_receiver,
+        token.clone(),
+    ));
+    let mut listen_handle = tokio::spawn(listen(
+        incoming_sender,
+        real_receiver,
+        verifier,
+        ImpairedQueue::new(impairment_receiver, link_rng(seed, agent_id, true)),
+        token,
+    ));
 
-        let write_handle = tokio::spawn(write(outgoing_receiver, real_sender, system_id));
-        let listen_handle = tokio::spawn(listen(incoming_sender, real_receiver));
+    // Whichever side stops first takes the whole link down with it
+    let result = select! {
+        result = &mut write_handle => result,
+        result = &mut listen_handle => result,
+    };
+    write_handle.abort();
+    listen_handle.abort();
 
-        let _ = tokio::join!(listen_handle, write_handle);
+    if let Ok(Err(err)) = result {
+        let _ = error_sender.send(err);
     }
 }
 
+/// Sends everything the simulation queues for this drone, framed by `framer`
+/// and then through the drone's `Impairment`. After an `Unregister` is
+/// queued the link stays open until the ground station acks it (see
+/// `listen`) or `unregister_timeout` passes, even if the simulation has
+/// already dropped the connection.
 pub async fn write(
     mut outgoing_receiver: SerpeDialectReceiver,
     mut real_sender: RealSender,
-    system_id: u8,
-) {
-    let endpoint = Endpoint::v2(MavLinkId::new(system_id, 0));
-    while let Some(msg) = outgoing_receiver.recv().await {
-        let frame = match msg {
-            SerpeDialect::Register(msg) => endpoint.next_frame(&msg).unwrap(),
-            SerpeDialect::Unregister(msg) => endpoint.next_frame(&msg).unwrap(),
-            SerpeDialect::Heartbeat(msg) => endpoint.next_frame(&msg).unwrap(),
-            SerpeDialect::MissionAccept(msg) => endpoint.next_frame(&msg).unwrap(),
-            SerpeDialect::MissionUpdate(msg) => endpoint.next_frame(&msg).unwrap(),
-            SerpeDialect::MissionFinished(msg) => endpoint.next_frame(&msg).unwrap(),
-            _ => {
-                continue;
+    mut framer: Framer,
+    unregister_timeout: Duration,
+    mut queue: ImpairedQueue,
+    tampering: TamperingReceiver,
+    token: CancellationToken,
+) -> Result<(), IoError> {
+    let mut previous_frame = None;
+    let mut unregistering = false;
+    let mut open = true;
+
+    // Frames still held back by the impairment go out before closing
+    while open || !queue.is_empty() {
+        select! {
+            _ = token.cancelled() => return Ok(()),
+            frame = queue.next_due() => {
+                real_sender.send(&frame).await.map_err(IoError::Send)?;
+                real_sender.flush().await.map_err(IoError::Send)?;
+            },
+            msg = outgoing_receiver.recv(), if open => {
+                let Some(msg) = msg else {
+                    open = false;
+                    continue;
+                };
+
+                let message: &dyn Message = match &msg {
+                    SerpeDialect::Register(msg) => msg,
+                    SerpeDialect::Unregister(msg) => msg,
+                    SerpeDialect::Heartbeat(msg) => msg,
+                    SerpeDialect::MissionAccept(msg) => msg,
+                    SerpeDialect::MissionItemRequest(msg) => msg,
+                    SerpeDialect::MissionAck(msg) => msg,
+                    SerpeDialect::MissionAbort(msg) => msg,
+                    SerpeDialect::MissionUpdate(msg) => msg,
+                    SerpeDialect::MissionFinished(msg) => msg,
+                    _ => {
+                        continue;
+                    }
+                };
+                let tampering = *tampering.borrow();
+                let frame = match tampering.strip_signatures {
+                    true => framer.next_unsigned_frame(message),
+                    false => framer.next_frame(message),
+                }
+                .map_err(IoError::Encode)?;
+                queue.push(frame.clone());
+                if tampering.replay {
+                    if let Some(previous_frame) = previous_frame.take() {
+                        queue.push(previous_frame);
+                    }
+                }
+                previous_frame = Some(frame);
+
+                // The timeout starts now so a dropped `Unregister` still ends
+                if matches!(msg, SerpeDialect::Unregister(_)) && !unregistering {
+                    unregistering = true;
+                    let token = token.clone();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(unregister_timeout).await;
+                        token.cancel();
+                    });
+                }
+            },
+        }
+    }
+
+    if unregistering {
+        token.cancelled().await;
+    }
+
+    Ok(())
+}
+
+/// Forwards what the ground station sends, through the same `Impairment` as
+/// `write`. Frames `verifier` turns down are dropped.
+pub async fn listen(
+    sender: SerpeDialectSender,
+    real_receiver: RealReceiver,
+    mut verifier: FrameVerifier,
+    mut queue: ImpairedQueue,
+    token: CancellationToken,
+) -> Result<(), IoError> {
+    // A frame takes several reads off the socket, so reading can't be one of
+    // the branches below: a frame coming due would cancel it halfway through.
+    // It runs on its own and hands whole frames over.
+    let (frame_sender, mut frames) = tokio::sync::mpsc::channel(256);
+    let read = read_frames(real_receiver, frame_sender);
+    tokio::pin!(read);
+    let mut read_error = None;
+
+    loop {
+        // Frames that arrived before the socket failed are still delivered
+        if queue.is_empty() {
+            if let Some(err) = read_error.take() {
+                return Err(err);
             }
+        }
+
+        let frame = select! {
+            biased;
+            _ = token.cancelled() => return Ok(()),
+            frame = queue.next_due() => frame,
+            Some(frame) = frames.recv() => {
+                queue.push(frame);
+                continue;
+            },
+            err = &mut read, if read_error.is_none() => {
+                read_error = Some(err);
+                continue;
+            },
         };
 
-        if let Err(_) = real_sender.send(&frame).await {
-            println!("Error sending frame!");
-            break;
+        if let Err(rejection) = verifier.check(&frame) {
+            println!(
+                "Dropped a frame from system {}: {}",
+                frame.system_id(),
+                rejection
+            );
+            continue;
+        }
+
+        // Frames outside of the dialect are not worth dropping the link over
+        let Ok(message) = frame.decode::<SerpeDialect>() else {
+            continue;
+        };
+
+        match message {
+            SerpeDialect::HeartbeatAck(_)
+            | SerpeDialect::MissionAcceptAck(_)
+            | SerpeDialect::MissionRequest(_)
+            | SerpeDialect::MissionCount(_)
+            | SerpeDialect::MissionItem(_)
+            | SerpeDialect::MissionFinishedAck(_) => forward(&sender, message),
+            SerpeDialect::UnregisterAck(_) => {
+                forward(&sender, message);
+                token.cancel();
+                return Ok(());
+            }
+            _ => {
+                continue;
+            }
         }
     }
 }
 
-pub async fn listen(sender: SerpeDialectSender, mut real_receiver: RealReceiver) {
-    while let Ok(frame) = real_receiver.recv().await {
-        match frame.decode::<SerpeDialect>() {
-            Ok(message) => {
-                match message {
-                    SerpeDialect::HeartbeatAck(_) => {
-                        // ignore hearbeat ack
-                    }
-                    SerpeDialect::MissionAcceptAck(_) => {
-                        let _ = sender.try_send(message);
-                    }
-                    SerpeDialect::MissionRequest(_) => {
-                        let _ = sender.try_send(message);
-                    }
-                    SerpeDialect::MissionFinishedAck(_) => {
-                        let _ = sender.try_send(message);
-                    }
-                    _ => {
-                        continue;
-                    }
-                }
+/// Reads frames until the socket fails, returning why.
+async fn read_frames(
+    mut real_receiver: RealReceiver,
+    frames: tokio::sync::mpsc::Sender<Frame<Versionless>>,
+) -> IoError {
+    loop {
+        match real_receiver.recv().await {
+            Ok(frame) => {
+                // `listen` owns the receiver for as long as this runs
+                let _ = frames.send(frame).await;
             }
-            Err(_) => {}
+            Err(err) => return IoError::Decode(err),
         }
     }
 }
+
+/// Hands `message` to the simulation. A full queue means the simulation is
+/// lagging behind, so the message is dropped. A closed one means the drone
+/// was deleted or the app is exiting: the link stays up for the `Unregister`
+/// all the same, until `write` is done with it.
+fn forward(sender: &SerpeDialectSender, message: SerpeDialect) {
+    let _ = sender.try_send(message);
+}
@@ -0,0 +1,291 @@
+//! Which MAVLink version a link speaks and how its frames are signed. Signing
+//! follows the MAVLink 2 scheme: a secret shared with the ground station, a
+//! link id per sender and a timestamp that has to keep going up, so a frame
+//! seen once is never accepted again.
+
+use core::fmt;
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use mavio::{
+    consts::SIGNATURE_SECRET_KEY_LENGTH,
+    prelude::{MavLinkVersion, Versionless, V1, V2},
+    protocol::{MavTimestamp, SecretKey, Signer, SigningConf},
+    utils::MavSha256,
+    Endpoint, Frame, MavLinkId, Message,
+};
+use serde::Deserialize;
+use tokio::sync::watch;
+
+/// How far a signed timestamp may run ahead of the clock before it is
+/// rejected, as the MAVLink spec suggests.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProtocolVersion {
+    V1,
+    #[default]
+    V2,
+}
+
+impl ProtocolVersion {
+    pub const ALL: [ProtocolVersion; 2] = [ProtocolVersion::V1, ProtocolVersion::V2];
+
+    fn mavlink(self) -> MavLinkVersion {
+        match self {
+            ProtocolVersion::V1 => MavLinkVersion::V1,
+            ProtocolVersion::V2 => MavLinkVersion::V2,
+        }
+    }
+}
+
+impl From<MavLinkVersion> for ProtocolVersion {
+    fn from(version: MavLinkVersion) -> Self {
+        match version {
+            MavLinkVersion::V1 => ProtocolVersion::V1,
+            MavLinkVersion::V2 => ProtocolVersion::V2,
+        }
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolVersion::V1 => write!(f, "MAVLink 1"),
+            ProtocolVersion::V2 => write!(f, "MAVLink 2"),
+        }
+    }
+}
+
+/// The MAVLink framing of one drone's link, both ways.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProtocolConfig {
+    pub version: ProtocolVersion,
+    /// Signs what the drone sends and checks what it receives. MAVLink 2
+    /// only.
+    pub signing: Option<SigningConfig>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SigningConfig {
+    /// The secret shared with the ground station, as 64 hex digits.
+    pub key: String,
+    /// Tells this drone's link apart in the signatures.
+    #[serde(default)]
+    pub link_id: u8,
+    /// Takes unsigned frames from the ground station too. Signed frames are
+    /// still checked.
+    #[serde(default)]
+    pub accept_unsigned: bool,
+}
+
+impl ProtocolConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let Some(signing) = &self.signing else {
+            return Ok(());
+        };
+        if self.version == ProtocolVersion::V1 {
+            return Err("signing needs MAVLink 2".to_string());
+        }
+        signing.secret().map(|_| ())
+    }
+}
+
+impl SigningConfig {
+    pub fn secret(&self) -> Result<SecretKey, String> {
+        let key = self.key.trim();
+        if key.len() != SIGNATURE_SECRET_KEY_LENGTH * 2 || !key.is_ascii() {
+            return Err(format!(
+                "signing key must be {} hex digits",
+                SIGNATURE_SECRET_KEY_LENGTH * 2
+            ));
+        }
+
+        let mut bytes = [0; SIGNATURE_SECRET_KEY_LENGTH];
+        for (byte, digits) in bytes.iter_mut().zip(key.as_bytes().chunks(2)) {
+            let digits = std::str::from_utf8(digits).expect("checked ascii above");
+            *byte = u8::from_str_radix(digits, 16)
+                .map_err(|_| format!("signing key has a non-hex digit in `{}`", digits))?;
+        }
+        Ok(SecretKey::from(bytes))
+    }
+}
+
+/// Ways to break the link's signing on purpose, set by the `UnsignedFrames`
+/// and `ReplayedFrames` faults.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Tampering {
+    /// Frames go out unsigned.
+    pub strip_signatures: bool,
+    /// Every frame is followed by another copy of the one before it.
+    pub replay: bool,
+}
+
+pub type TamperingSender = watch::Sender<Tampering>;
+pub type TamperingReceiver = watch::Receiver<Tampering>;
+
+/// Turns messages into frames of the link's version, signed if the link is.
+pub struct Framer {
+    endpoint: Endpoint<Versionless>,
+    version: ProtocolVersion,
+    signing: Option<Signing>,
+}
+
+struct Signing {
+    conf: SigningConf,
+    sha: MavSha256,
+}
+
+impl Framer {
+    pub fn new(system_id: u8, protocol: &ProtocolConfig) -> Result<Self, String> {
+        protocol.validate()?;
+        let signing = match &protocol.signing {
+            Some(signing) => Some(Signing {
+                conf: SigningConf {
+                    link_id: signing.link_id,
+                    timestamp: MavTimestamp::default(),
+                    secret: signing.secret()?,
+                },
+                sha: MavSha256::default(),
+            }),
+            None => None,
+        };
+
+        Ok(Self {
+            endpoint: Endpoint::versionless(MavLinkId::new(system_id, 0)),
+            version: protocol.version,
+            signing,
+        })
+    }
+
+    /// Sends as `system_id` from now on, with the sequence starting over.
+    /// Signing timestamps carry on, the ground station remembers them.
+    pub fn set_system_id(&mut self, system_id: u8) {
+        self.endpoint = Endpoint::versionless(MavLinkId::new(system_id, 0));
+    }
+
+    pub fn next_frame(&mut self, message: &dyn Message) -> mavio::Result<Frame<Versionless>> {
+        let mut frame = self.next_unsigned_frame(message)?;
+        let Some(signing) = &mut self.signing else {
+            return Ok(frame);
+        };
+
+        // Two frames in the same 10 µs tick still need different stamps
+        let now = MavTimestamp::from_system_time(SystemTime::now()).as_raw_u64();
+        let last = signing.conf.timestamp.as_raw_u64();
+        signing.conf.timestamp = MavTimestamp::from_raw_u64(now.max(last + 1));
+
+        // mavio raises the signed flag after the checksum, which covers the
+        // flag, was computed. Compute it again and sign over the right one.
+        signing.conf.apply(&mut frame, &mut signing.sha);
+        frame.upgrade_with_crc_extra(message.crc_extra());
+        signing.conf.apply(&mut frame, &mut signing.sha);
+
+        Ok(frame)
+    }
+
+    /// Like `next_frame`, but never signed. For the `UnsignedFrames` fault.
+    pub fn next_unsigned_frame(
+        &mut self,
+        message: &dyn Message,
+    ) -> mavio::Result<Frame<Versionless>> {
+        match self.version {
+            ProtocolVersion::V1 => self.endpoint.next_frame::<V1>(message),
+            ProtocolVersion::V2 => self.endpoint.next_frame::<V2>(message),
+        }
+    }
+}
+
+/// Why `FrameVerifier` turned a frame down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    WrongVersion,
+    Unsigned,
+    BadSignature,
+    /// The timestamp is not past the last one from the same sender, or too
+    /// far in the future.
+    Replayed,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::WrongVersion => write!(f, "wrong MAVLink version"),
+            Rejection::Unsigned => write!(f, "unsigned"),
+            Rejection::BadSignature => write!(f, "bad signature"),
+            Rejection::Replayed => write!(f, "replayed"),
+        }
+    }
+}
+
+/// Checks incoming frames against the link's version and signing.
+pub struct FrameVerifier {
+    version: ProtocolVersion,
+    signing: Option<Verification>,
+}
+
+struct Verification {
+    secret: SecretKey,
+    accept_unsigned: bool,
+    sha: MavSha256,
+    /// Last timestamp seen per system, component and link id.
+    last_timestamps: HashMap<(u8, u8, u8), u64>,
+}
+
+impl FrameVerifier {
+    pub fn new(protocol: &ProtocolConfig) -> Result<Self, String> {
+        protocol.validate()?;
+        let signing = match &protocol.signing {
+            Some(signing) => Some(Verification {
+                secret: signing.secret()?,
+                accept_unsigned: signing.accept_unsigned,
+                sha: MavSha256::default(),
+                last_timestamps: HashMap::new(),
+            }),
+            None => None,
+        };
+
+        Ok(Self {
+            version: protocol.version,
+            signing,
+        })
+    }
+
+    pub fn check(&mut self, frame: &Frame<Versionless>) -> Result<(), Rejection> {
+        if frame.version() != self.version.mavlink() {
+            return Err(Rejection::WrongVersion);
+        }
+        let Some(signing) = &mut self.signing else {
+            return Ok(());
+        };
+        let Some(signature) = frame.signature() else {
+            return match signing.accept_unsigned {
+                true => Ok(()),
+                false => Err(Rejection::Unsigned),
+            };
+        };
+
+        if !Signer::new(&mut signing.sha).validate(frame, signature, &signing.secret) {
+            return Err(Rejection::BadSignature);
+        }
+
+        let timestamp = signature.timestamp().as_raw_u64();
+        let horizon = MavTimestamp::from_system_time(SystemTime::now() + MAX_CLOCK_SKEW);
+        if timestamp > horizon.as_raw_u64() {
+            return Err(Rejection::Replayed);
+        }
+        let sender = (frame.system_id(), frame.component_id(), signature.link_id());
+        match signing.last_timestamps.get(&sender) {
+            Some(last) if timestamp <= *last => Err(Rejection::Replayed),
+            _ => {
+                signing.last_timestamps.insert(sender, timestamp);
+                Ok(())
+            }
+        }
+    }
+}
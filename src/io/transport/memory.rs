@@ -0,0 +1,87 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{LazyLock, Mutex},
+};
+
+use tokio::{
+    io::{duplex, DuplexStream},
+    sync::mpsc,
+};
+
+use super::{Transport, TransportReader, TransportWriter};
+
+/// Bytes either side can write before the other reads them.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Listeners by name, standing in for ports.
+static LISTENERS: LazyLock<Mutex<HashMap<String, mpsc::UnboundedSender<DuplexStream>>>> =
+    LazyLock::new(Default::default);
+
+/// Connects to the `MemoryListener` bound to `name` in this process.
+pub struct MemoryTransport {
+    pub name: String,
+}
+
+impl Transport for MemoryTransport {
+    async fn open(self) -> io::Result<(TransportReader, TransportWriter)> {
+        let (ours, theirs) = duplex(BUFFER_SIZE);
+
+        let listeners = LISTENERS.lock().unwrap();
+        let accepted = listeners
+            .get(&self.name)
+            .is_some_and(|listener| listener.send(theirs).is_ok());
+        if !accepted {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("nothing listening on memory:{}", self.name),
+            ));
+        }
+
+        let (reader, writer) = tokio::io::split(ours);
+        Ok((Box::new(reader), Box::new(writer)))
+    }
+}
+
+/// The ground-station side of `MemoryTransport`. The name is free again once
+/// the listener is dropped.
+pub struct MemoryListener {
+    name: String,
+    incoming: mpsc::UnboundedReceiver<DuplexStream>,
+}
+
+impl MemoryListener {
+    pub fn bind(name: &str) -> io::Result<Self> {
+        let mut listeners = LISTENERS.lock().unwrap();
+        if listeners.contains_key(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("memory:{} is already bound", name),
+            ));
+        }
+
+        let (sender, incoming) = mpsc::unbounded_channel();
+        listeners.insert(name.to_string(), sender);
+        Ok(Self {
+            name: name.to_string(),
+            incoming,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub async fn accept(&mut self) -> io::Result<DuplexStream> {
+        self.incoming
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+impl Drop for MemoryListener {
+    fn drop(&mut self) {
+        LISTENERS.lock().unwrap().remove(&self.name);
+    }
+}
@@ -0,0 +1,192 @@
+//! How a drone's MAVLink stream reaches the ground station. Every transport
+//! comes down to a reader and a writer, which is all `listen` and `write`
+//! need.
+
+use std::{future::Future, io, path::PathBuf};
+
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use self::{
+    memory::MemoryTransport,
+    shared::{SharedPorts, SharedTcpServer},
+    tcp::{TcpClient, TcpServer},
+    udp::UdpTransport,
+};
+
+pub mod memory;
+#[cfg(unix)]
+pub mod pty;
+pub mod shared;
+pub mod tcp;
+pub mod udp;
+
+/// Where a `tcp_server` drone listens unless told otherwise, MAVLink's usual
+/// TCP port.
+pub const DEFAULT_SERVER_ADDRESS: &str = "0.0.0.0:5760";
+/// Lets the OS pick the local port.
+pub const DEFAULT_UDP_BIND: &str = "0.0.0.0:0";
+
+pub type TransportReader = Box<dyn AsyncRead + Send + Unpin>;
+pub type TransportWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// One way of reaching a ground station.
+pub trait Transport {
+    /// Opens the link, ready for the `Register` handshake. Writes only have
+    /// to go out once the writer is flushed.
+    fn open(self) -> impl Future<Output = io::Result<(TransportReader, TransportWriter)>> + Send;
+}
+
+/// Which `Transport` a drone uses. The ones that dial out go to the
+/// ground-station address, the others listen where they are told to.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TransportConfig {
+    /// Connects to the ground station over TCP.
+    #[default]
+    TcpClient,
+    /// Waits for the ground station to connect over TCP. A `shared` port
+    /// serves every drone that uses it over one connection, see
+    /// `shared::SharedTcpServer`.
+    TcpServer {
+        #[serde(default = "default_server_address")]
+        address: String,
+        #[serde(default)]
+        shared: bool,
+    },
+    /// One frame per datagram, sent to the ground-station address.
+    Udp {
+        #[serde(default = "default_udp_bind")]
+        bind: String,
+    },
+    /// Connects to the `MemoryListener` named after the ground-station
+    /// address, for tests that want no sockets at all.
+    Memory,
+    /// Opens a pseudo-terminal for the ground station to use as a serial
+    /// port, optionally symlinked to `link` for a name that survives
+    /// reconnects.
+    Pty { link: Option<PathBuf> },
+}
+
+fn default_server_address() -> String {
+    DEFAULT_SERVER_ADDRESS.to_string()
+}
+
+fn default_udp_bind() -> String {
+    DEFAULT_UDP_BIND.to_string()
+}
+
+impl TransportConfig {
+    /// One of each kind, with their default settings.
+    pub fn kinds() -> [Self; 5] {
+        [
+            Self::TcpClient,
+            Self::TcpServer {
+                address: default_server_address(),
+                shared: false,
+            },
+            Self::Udp {
+                bind: default_udp_bind(),
+            },
+            Self::Memory,
+            Self::Pty { link: None },
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::TcpClient => "TCP client",
+            Self::TcpServer { .. } => "TCP server",
+            Self::Udp { .. } => "UDP",
+            Self::Memory => "In-memory",
+            Self::Pty { .. } => "PTY",
+        }
+    }
+
+    pub fn is_same_kind(&self, other: &Self) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    /// Whether opening waits on the ground station, so it can't be held to
+    /// the handshake timeout.
+    pub fn waits_for_peer(&self) -> bool {
+        matches!(self, Self::TcpServer { .. })
+    }
+
+    /// The far end of the link, for error messages.
+    pub fn describe(&self, address: &str) -> String {
+        match self {
+            Self::TcpClient | Self::Udp { .. } => address.to_string(),
+            Self::TcpServer {
+                address,
+                shared: false,
+            } => format!("ground station on {}", address),
+            Self::TcpServer {
+                address,
+                shared: true,
+            } => format!("ground station on shared port {}", address),
+            Self::Memory => format!("memory:{}", address),
+            Self::Pty { .. } => "pseudo-terminal".to_string(),
+        }
+    }
+
+    /// Opens the link to the ground station at `address`.
+    pub async fn open(
+        &self,
+        address: &str,
+        shared_ports: &SharedPorts,
+    ) -> io::Result<(TransportReader, TransportWriter)> {
+        match self {
+            Self::TcpClient => {
+                TcpClient {
+                    address: address.to_string(),
+                }
+                .open()
+                .await
+            }
+            Self::TcpServer {
+                address,
+                shared: false,
+            } => {
+                TcpServer {
+                    address: address.clone(),
+                }
+                .open()
+                .await
+            }
+            Self::TcpServer {
+                address,
+                shared: true,
+            } => {
+                SharedTcpServer {
+                    address: address.clone(),
+                    ports: shared_ports.clone(),
+                }
+                .open()
+                .await
+            }
+            Self::Udp { bind } => {
+                UdpTransport {
+                    bind: bind.clone(),
+                    remote: address.to_string(),
+                }
+                .open()
+                .await
+            }
+            Self::Memory => {
+                MemoryTransport {
+                    name: address.to_string(),
+                }
+                .open()
+                .await
+            }
+            #[cfg(unix)]
+            Self::Pty { link } => pty::PtyTransport { link: link.clone() }.open().await,
+            #[cfg(not(unix))]
+            Self::Pty { .. } => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "pseudo-terminals need a unix system",
+            )),
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    ffi::CStr,
+    fs::File,
+    io::{self, Read, Write},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+
+use tokio::io::{unix::AsyncFd, AsyncRead, AsyncWrite, ReadBuf};
+
+use super::{Transport, TransportReader, TransportWriter};
+
+/// Opens a pseudo-terminal in raw mode. The ground station opens the other
+/// end like any serial port; its path is printed, and symlinked to `link`
+/// when set.
+pub struct PtyTransport {
+    pub link: Option<PathBuf>,
+}
+
+impl Transport for PtyTransport {
+    async fn open(self) -> io::Result<(TransportReader, TransportWriter)> {
+        let (master, slave) = open_pty()?;
+        let path = slave_path(&slave)?;
+
+        match &self.link {
+            Some(link) => {
+                // Only ever replace a link left behind by an earlier attempt
+                if link.is_symlink() {
+                    std::fs::remove_file(link)?;
+                }
+                std::os::unix::fs::symlink(&path, link)?;
+                println!("Serial port at {} ({})", link.display(), path.display());
+            }
+            None => println!("Serial port at {}", path.display()),
+        }
+
+        let pty = Arc::new(Pty {
+            master: AsyncFd::new(File::from(master))?,
+            _slave: slave,
+            link: self.link,
+        });
+        Ok((Box::new(PtyHalf(pty.clone())), Box::new(PtyHalf(pty))))
+    }
+}
+
+struct Pty {
+    master: AsyncFd<File>,
+    /// Held open so the ground station can close and reopen its end without
+    /// the master seeing a hang-up.
+    _slave: OwnedFd,
+    link: Option<PathBuf>,
+}
+
+impl Drop for Pty {
+    fn drop(&mut self) {
+        if let Some(link) = &self.link {
+            let _ = std::fs::remove_file(link);
+        }
+    }
+}
+
+/// Either direction of the pseudo-terminal, both go through the master.
+struct PtyHalf(Arc<Pty>);
+
+impl AsyncRead for PtyHalf {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = ready!(self.0.master.poll_read_ready(cx))?;
+            let unfilled = buf.initialize_unfilled();
+            match guard.try_io(|master| master.get_ref().read(unfilled)) {
+                Ok(result) => {
+                    let len = result?;
+                    buf.advance(len);
+                    return Poll::Ready(Ok(()));
+                }
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl AsyncWrite for PtyHalf {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.0.master.poll_write_ready(cx))?;
+            match guard.try_io(|master| master.get_ref().write(buf)) {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Returns the non-blocking master and the raw-mode slave.
+fn open_pty() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut master = -1;
+    let mut slave = -1;
+    // SAFETY: only the two out-pointers are passed, the rest may be null
+    let result = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: openpty succeeded, so both are open descriptors owned by us
+    let (master, slave) = unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+
+    // Without raw mode the line discipline echoes frames back and mangles
+    // bytes that happen to look like control characters
+    // SAFETY: termios is plain data, filled in by tcgetattr before use
+    unsafe {
+        let mut termios = std::mem::zeroed::<libc::termios>();
+        if libc::tcgetattr(slave.as_raw_fd(), &mut termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::cfmakeraw(&mut termios);
+        if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let flags = libc::fcntl(master.as_raw_fd(), libc::F_GETFL);
+        if flags < 0 || libc::fcntl(master.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok((master, slave))
+}
+
+fn slave_path(slave: &OwnedFd) -> io::Result<PathBuf> {
+    let mut name = [0 as libc::c_char; 128];
+    // SAFETY: the buffer outlives the call and its length is passed along
+    let result = unsafe { libc::ttyname_r(slave.as_raw_fd(), name.as_mut_ptr(), name.len()) };
+    if result != 0 {
+        return Err(io::Error::from_raw_os_error(result));
+    }
+    // SAFETY: ttyname_r succeeded, so the buffer holds a nul-terminated path
+    let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+    Ok(PathBuf::from(name.to_string_lossy().into_owned()))
+}
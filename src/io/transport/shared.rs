@@ -0,0 +1,336 @@
+//! One listening port for several drones. The ground station connects once
+//! and every drone on the port talks over that link, told apart by system
+//! id. Frames from the ground station go to the drone whose system id is in
+//! their header. A `RegisterAck` goes to the oldest unanswered `Register`,
+//! since the drone has no id yet. Drones on the port may speak different
+//! MAVLink versions, frames are passed on as they are.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use mavio::{prelude::Versionless, AsyncReceiver, AsyncSender, Frame, Receiver};
+use tokio::{
+    io::{duplex, AsyncWrite, AsyncWriteExt, DuplexStream},
+    net::{tcp::OwnedReadHalf, TcpListener, TcpStream},
+    select,
+    sync::{
+        mpsc::{self, error::TrySendError},
+        oneshot, Mutex,
+    },
+};
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+use crate::mavlink::dialects::SerpeDialect;
+
+use super::{Transport, TransportReader, TransportWriter};
+
+/// Bytes the ground station can send a drone before it reads them.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Frames queued for a drone on top of `BUFFER_SIZE`. A drone that lets
+/// this many pile up is dropped from the port, so it can't hold up the
+/// others.
+const QUEUE_SIZE: usize = 256;
+
+/// Joins the shared port at `address`, binding it if no drone has yet.
+pub struct SharedTcpServer {
+    pub address: String,
+    pub ports: SharedPorts,
+}
+
+impl Transport for SharedTcpServer {
+    async fn open(self) -> io::Result<(TransportReader, TransportWriter)> {
+        let port = self.ports.port(&self.address).await?;
+
+        let (reply, opened) = oneshot::channel();
+        port.send(Join { reply })
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        opened
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+/// The shared ports by address. Once bound, a port stays open until the IO
+/// runtime shuts down.
+#[derive(Clone)]
+pub struct SharedPorts {
+    ports: Arc<Mutex<HashMap<String, mpsc::UnboundedSender<Join>>>>,
+    token: CancellationToken,
+}
+
+impl SharedPorts {
+    pub fn new(token: CancellationToken) -> Self {
+        Self {
+            ports: Default::default(),
+            token,
+        }
+    }
+
+    async fn port(&self, address: &str) -> io::Result<mpsc::UnboundedSender<Join>> {
+        let mut ports = self.ports.lock().await;
+        if let Some(port) = ports.get(address).filter(|port| !port.is_closed()) {
+            return Ok(port.clone());
+        }
+
+        let listener = TcpListener::bind(address).await?;
+        let (sender, joins) = mpsc::unbounded_channel();
+        tokio::spawn(run_port(
+            address.to_string(),
+            listener,
+            joins,
+            self.token.clone(),
+        ));
+        ports.insert(address.to_string(), sender.clone());
+        Ok(sender)
+    }
+}
+
+/// A drone asking for its end of the link, answered once the ground station
+/// is connected.
+struct Join {
+    reply: oneshot::Sender<(TransportReader, TransportWriter)>,
+}
+
+enum PortEvent {
+    /// A whole frame one of the drones wrote.
+    Frame { drone: u64, bytes: Vec<u8> },
+    /// The drone's link ended.
+    Closed { drone: u64 },
+}
+
+struct Member {
+    frames: mpsc::Sender<Frame<Versionless>>,
+    /// Known once its `RegisterAck` went through.
+    system_id: Option<u8>,
+    /// Stops `forward` when the member leaves, even if it is stuck writing.
+    _forwarding: DropGuard,
+}
+
+async fn run_port(
+    address: String,
+    listener: TcpListener,
+    mut joins: mpsc::UnboundedReceiver<Join>,
+    token: CancellationToken,
+) {
+    let mut waiting = Vec::new();
+
+    loop {
+        let stream = select! {
+            _ = token.cancelled() => return,
+            join = joins.recv() => {
+                match join {
+                    Some(join) => waiting.push(join),
+                    None => return,
+                }
+                continue;
+            },
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    println!("Shared port {} failed to accept: {}", address, err);
+                    continue;
+                }
+            },
+        };
+
+        println!("Ground station connected to shared port {}", address);
+        serve(stream, &mut joins, std::mem::take(&mut waiting), &token).await;
+        println!("Ground station left shared port {}", address);
+    }
+}
+
+/// Runs one ground-station connection until it drops. Every drone on it
+/// loses its link at the same time, and joins again to wait for the next.
+async fn serve(
+    stream: TcpStream,
+    joins: &mut mpsc::UnboundedReceiver<Join>,
+    waiting: Vec<Join>,
+    token: &CancellationToken,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let (events_sender, mut events) = mpsc::unbounded_channel();
+    let mut members = HashMap::new();
+    let mut next_member = 0;
+    // Drones whose `Register` went out, oldest first
+    let mut registering = VecDeque::new();
+
+    let mut admit = |join: Join, members: &mut HashMap<u64, Member>| {
+        let drone = next_member;
+        next_member += 1;
+
+        let (ours, theirs) = duplex(BUFFER_SIZE);
+        let writer = MemberWriter {
+            drone,
+            events: events_sender.clone(),
+            frame: Vec::new(),
+        };
+        if join
+            .reply
+            .send((Box::new(theirs), Box::new(writer)))
+            .is_ok()
+        {
+            let (frames, queued) = mpsc::channel(QUEUE_SIZE);
+            let forwarding = CancellationToken::new();
+            tokio::spawn(forward(queued, ours, forwarding.clone()));
+            let member = Member {
+                frames,
+                system_id: None,
+                _forwarding: forwarding.drop_guard(),
+            };
+            members.insert(drone, member);
+        }
+    };
+    for join in waiting {
+        admit(join, &mut members);
+    }
+
+    // Reading a frame can't be cancelled halfway, see `io::listen`
+    let (frame_sender, mut frames) = mpsc::channel(256);
+    let read = read_frames(AsyncReceiver::versionless(reader), frame_sender);
+    tokio::pin!(read);
+
+    loop {
+        select! {
+            _ = token.cancelled() => return,
+            _ = &mut read => return,
+            Some(join) = joins.recv() => admit(join, &mut members),
+            Some(event) = events.recv() => match event {
+                PortEvent::Frame { drone, bytes } => {
+                    if is_register(&bytes) {
+                        registering.push_back(drone);
+                    }
+                    if writer.write_all(&bytes).await.is_err() {
+                        return;
+                    }
+                },
+                PortEvent::Closed { drone } => {
+                    members.remove(&drone);
+                    registering.retain(|registering| *registering != drone);
+                },
+            },
+            Some(frame) = frames.recv() => {
+                let drone = match frame.decode::<SerpeDialect>() {
+                    Ok(SerpeDialect::RegisterAck(ack)) => {
+                        let drone = registering.pop_front();
+                        if let Some(member) = drone.and_then(|drone| members.get_mut(&drone)) {
+                            member.system_id = Some(ack.system_id);
+                        }
+                        drone
+                    }
+                    _ => members
+                        .iter()
+                        .find(|(_, member)| member.system_id == Some(frame.system_id()))
+                        .map(|(drone, _)| *drone),
+                };
+                // Meant for a drone that isn't on the port (anymore)
+                let Some(drone) = drone else {
+                    continue;
+                };
+                let Some(member) = members.get(&drone) else {
+                    continue;
+                };
+
+                // Waiting here would stall every drone on the port
+                if let Err(err) = member.frames.try_send(frame) {
+                    if let TrySendError::Full(_) = err {
+                        println!("Dropped a drone from a shared port, it stopped reading");
+                    }
+                    members.remove(&drone);
+                    registering.retain(|registering| *registering != drone);
+                }
+            },
+        }
+    }
+}
+
+/// Writes a member's frames to its end of the link, at whatever pace the
+/// drone reads them.
+async fn forward(
+    mut frames: mpsc::Receiver<Frame<Versionless>>,
+    link: DuplexStream,
+    token: CancellationToken,
+) {
+    let mut sender = AsyncSender::versionless(link);
+    loop {
+        let frame = select! {
+            _ = token.cancelled() => return,
+            frame = frames.recv() => match frame {
+                Some(frame) => frame,
+                None => return,
+            },
+        };
+        select! {
+            _ = token.cancelled() => return,
+            sent = sender.send(&frame) => if sent.is_err() {
+                return;
+            },
+        }
+    }
+}
+
+async fn read_frames(
+    mut receiver: AsyncReceiver<OwnedReadHalf, Versionless>,
+    frames: mpsc::Sender<Frame<Versionless>>,
+) {
+    while let Ok(frame) = receiver.recv().await {
+        if frames.send(frame).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn is_register(bytes: &[u8]) -> bool {
+    Receiver::versionless(bytes)
+        .recv()
+        .is_ok_and(|frame| matches!(frame.decode(), Ok(SerpeDialect::Register(_))))
+}
+
+/// A drone's writing end, handing each flushed frame to the port.
+struct MemberWriter {
+    drone: u64,
+    events: mpsc::UnboundedSender<PortEvent>,
+    frame: Vec<u8>,
+}
+
+impl AsyncWrite for MemberWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().frame.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.frame.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+
+        let event = PortEvent::Frame {
+            drone: this.drone,
+            bytes: std::mem::take(&mut this.frame),
+        };
+        match this.events.send(event) {
+            Ok(()) => Poll::Ready(Ok(())),
+            Err(_) => Poll::Ready(Err(io::Error::from(io::ErrorKind::BrokenPipe))),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+impl Drop for MemberWriter {
+    fn drop(&mut self) {
+        let _ = self.events.send(PortEvent::Closed { drone: self.drone });
+    }
+}
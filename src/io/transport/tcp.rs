@@ -0,0 +1,36 @@
+use std::io;
+
+use tokio::net::{TcpListener, TcpStream};
+
+use super::{Transport, TransportReader, TransportWriter};
+
+/// Connects to the ground station at `address`.
+pub struct TcpClient {
+    pub address: String,
+}
+
+impl Transport for TcpClient {
+    async fn open(self) -> io::Result<(TransportReader, TransportWriter)> {
+        let stream = TcpStream::connect(&self.address).await?;
+        Ok(split(stream))
+    }
+}
+
+/// Listens on `address` until the ground station connects. The port is only
+/// held while waiting, so the next attempt can bind it again.
+pub struct TcpServer {
+    pub address: String,
+}
+
+impl Transport for TcpServer {
+    async fn open(self) -> io::Result<(TransportReader, TransportWriter)> {
+        let listener = TcpListener::bind(&self.address).await?;
+        let (stream, _) = listener.accept().await?;
+        Ok(split(stream))
+    }
+}
+
+fn split(stream: TcpStream) -> (TransportReader, TransportWriter) {
+    let (reader, writer) = stream.into_split();
+    (Box::new(reader), Box::new(writer))
+}
@@ -0,0 +1,119 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::UdpSocket,
+};
+
+use super::{Transport, TransportReader, TransportWriter};
+
+/// Largest payload a UDP datagram can carry.
+const MAX_DATAGRAM: usize = 65_507;
+
+/// Binds `bind` and exchanges datagrams with `remote` only, the way MAVLink
+/// radios and SITL do.
+pub struct UdpTransport {
+    pub bind: String,
+    pub remote: String,
+}
+
+impl Transport for UdpTransport {
+    async fn open(self) -> io::Result<(TransportReader, TransportWriter)> {
+        let socket = UdpSocket::bind(&self.bind).await?;
+        socket.connect(&self.remote).await?;
+        let (reader, writer) = split(socket);
+        Ok((Box::new(reader), Box::new(writer)))
+    }
+}
+
+/// Wraps a connected socket as a byte stream. Exposed so a ground station
+/// can talk to `UdpTransport` the same way.
+pub fn split(socket: UdpSocket) -> (UdpReader, UdpWriter) {
+    let socket = Arc::new(socket);
+    let reader = UdpReader {
+        socket: socket.clone(),
+        datagram: vec![0; MAX_DATAGRAM],
+        start: 0,
+        end: 0,
+    };
+    let writer = UdpWriter {
+        socket,
+        datagram: Vec::new(),
+    };
+    (reader, writer)
+}
+
+/// Hands out received datagrams as a stream of bytes.
+pub struct UdpReader {
+    socket: Arc<UdpSocket>,
+    datagram: Vec<u8>,
+    /// What is left of the last datagram.
+    start: usize,
+    end: usize,
+}
+
+impl AsyncRead for UdpReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if this.start == this.end {
+            let mut datagram = ReadBuf::new(&mut this.datagram);
+            ready!(this.socket.poll_recv(cx, &mut datagram))?;
+            this.start = 0;
+            this.end = datagram.filled().len();
+        }
+
+        let len = buf.remaining().min(this.end - this.start);
+        buf.put_slice(&this.datagram[this.start..this.start + len]);
+        this.start += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Collects writes and sends them as one datagram on flush, so a frame is
+/// never split across datagrams.
+pub struct UdpWriter {
+    socket: Arc<UdpSocket>,
+    datagram: Vec<u8>,
+}
+
+impl AsyncWrite for UdpWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let len = buf.len().min(MAX_DATAGRAM - this.datagram.len());
+        if len == 0 && !buf.is_empty() {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "datagram too large, flush first",
+            )));
+        }
+        this.datagram.extend_from_slice(&buf[..len]);
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.datagram.is_empty() {
+            ready!(this.socket.poll_send(cx, &this.datagram))?;
+            this.datagram.clear();
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
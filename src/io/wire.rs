@@ -0,0 +1,74 @@
+//! The one place where positions are converted to and from their MAVLink
+//! representation. Every position field in the dialect is degrees * 1e7
+//! stored in an `int32_t` (the `degE7` convention used by `GLOBAL_POSITION_INT`
+//! and `MISSION_ITEM_INT`), which keeps ~1 cm of precision everywhere on Earth.
+//! Altitudes are millimetres above the ground in an `int32_t`.
+
+use crate::{
+    domain::{battery::Battery, coordinates::Coordinates},
+    mavlink::dialects::serpe_dialect::messages::{
+        Heartbeat, MissionItem, MissionRequest, MissionUpdate, Register,
+    },
+};
+
+const DEG_E7: f64 = 1e7;
+const MM_PER_METRE: f64 = 1e3;
+
+pub fn encode_degrees(degrees: f64) -> i32 {
+    (degrees * DEG_E7).round() as i32
+}
+
+pub fn decode_degrees(deg_e7: i32) -> f64 {
+    deg_e7 as f64 / DEG_E7
+}
+
+pub fn encode_altitude(metres: f64) -> i32 {
+    (metres * MM_PER_METRE).round() as i32
+}
+
+pub fn register(agent_id: u32, coordinates: &Coordinates) -> Register {
+    Register {
+        agent_id,
+        latitude: encode_degrees(coordinates.latitude),
+        longitude: encode_degrees(coordinates.longitude),
+        altitude: encode_altitude(coordinates.altitude),
+    }
+}
+
+/// `battery_remaining` is a percentage, or -1 when the drone has no battery
+/// model (same as MAVLink's `SYS_STATUS`).
+pub fn heartbeat(coordinates: &Coordinates, battery: Option<&Battery>) -> Heartbeat {
+    Heartbeat {
+        latitude: encode_degrees(coordinates.latitude),
+        longitude: encode_degrees(coordinates.longitude),
+        altitude: encode_altitude(coordinates.altitude),
+        battery_remaining: battery.map_or(-1, |battery| (battery.fraction() * 100.0).round() as i8),
+    }
+}
+
+pub fn mission_update(coordinates: &Coordinates, current_waypoint: usize) -> MissionUpdate {
+    MissionUpdate {
+        current_latitude: encode_degrees(coordinates.latitude),
+        current_longitude: encode_degrees(coordinates.longitude),
+        current_altitude: encode_altitude(coordinates.altitude),
+        // Uploads are capped at u16::MAX items by MISSION_COUNT
+        current_waypoint: current_waypoint as u16,
+    }
+}
+
+pub fn mission_target(request: &MissionRequest) -> Coordinates {
+    Coordinates {
+        latitude: decode_degrees(request.target_latitude),
+        longitude: decode_degrees(request.target_longitude),
+        // Waypoints are flown at the drone's cruise altitude
+        altitude: 0.0,
+    }
+}
+
+pub fn mission_item(item: &MissionItem) -> Coordinates {
+    Coordinates {
+        latitude: decode_degrees(item.latitude),
+        longitude: decode_degrees(item.longitude),
+        altitude: 0.0,
+    }
+}
@@ -0,0 +1,13 @@
+pub mod cli;
+pub mod config;
+pub mod domain;
+pub mod ground_station;
+pub mod io;
+pub mod misc;
+pub mod scenario;
+pub mod simulation;
+pub mod ui;
+
+pub mod mavlink {
+    include!(concat!(env!("OUT_DIR"), "/mavlink/mod.rs"));
+}
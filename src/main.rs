@@ -1,105 +1,95 @@
-use bevy::prelude::*;
-use bevy_egui::{EguiPlugin, EguiSettings};
-use domain::{coordinates::COORDS_ZOOM, mission::{
-    system_mission_update_coordinates, system_mission_update_sender, system_mission_updater,
-    MissionUpdateTimer,
-}};
-use io::{run_io, IOResource};
-use misc::{
-    heartbeat::{system_heartbeat, HeartbeatTimer},
-    id_tracker::DroneIdTracker,
-    selected_drone::SelectedDrone,
-};
-use tokio_util::sync::CancellationToken;
-use ui::{
-    render_drones::{system_despawn_entities, system_render_drones},
-    system_drone_ui_left_panel, system_drone_ui_right_panel,
-};
-
-pub mod domain;
-pub mod io;
-pub mod misc;
-pub mod ui;
+use std::time::Duration;
 
-mod mavlink {
-    include!(concat!(env!("OUT_DIR"), "/mavlink/mod.rs"));
-}
+use bevy::{app::ScheduleRunnerPlugin, prelude::*};
+use clap::Parser;
+use simulator::{
+    cli::Cli,
+    config::Config,
+    domain::connection::DefaultConnectionPolicy,
+    io::{run_io, IOResource},
+    misc::{clock::SimulationClock, rng::SimulationRng},
+    scenario::Scenario,
+    simulation::{ExitSignal, InitialDrones, SimulationPlugin},
+    ui::UiPlugin,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 
-const GUI_SCALE_FACTOR: f32 = 1.5;
+const HEADLESS_FRAME_RATE: f64 = 60.0;
 
-use bevy::input::keyboard::KeyCode;
+/// Upper bound on how long exiting waits for drones to unregister.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Failed to load configuration: {}", err);
+            std::process::exit(1);
+        }
+    };
+    let scenario = match cli.scenario.as_deref().map(Scenario::load).transpose() {
+        Ok(scenario) => scenario,
+        Err(err) => {
+            eprintln!("Failed to load scenario: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let seed = cli
+        .seed
+        .or(scenario.as_ref().and_then(|scenario| scenario.seed))
+        .unwrap_or_else(rand::random);
+    println!("Simulation seed: {}", seed);
+
     let (tx, rx) = tokio::sync::mpsc::channel(1000);
 
     let token = CancellationToken::new();
     let io_token = token.clone();
+    let tracker = TaskTracker::new();
+    let io_tracker = tracker.clone();
 
     tokio::spawn(async move {
-        run_io(rx, io_token).await;
+        run_io(rx, io_token, io_tracker).await;
     });
 
-    App::new()
-        .insert_resource(DroneIdTracker::default())
-        .insert_resource(SelectedDrone::default())
-        .insert_resource(EguiSettings {
-            scale_factor: GUI_SCALE_FACTOR,
-            ..Default::default()
-        })
-        .insert_resource(IOResource { sender: tx })
-        .insert_resource(HeartbeatTimer::default())
-        .insert_resource(MissionUpdateTimer::default())
-        .add_plugins(DefaultPlugins)
-        .add_plugins(EguiPlugin)
-        .add_systems(Startup, system_setup)
-        .add_systems(Update, system_drone_ui_left_panel)
-        .add_systems(Update, system_drone_ui_right_panel)
-        .add_systems(Update, system_despawn_entities)
-        .add_systems(Update, system_render_drones)
-        .add_systems(Update, system_mission_updater)
-        .add_systems(Update, system_mission_update_sender)
-        .add_systems(Update, system_mission_update_coordinates)
-        .add_systems(Update, system_heartbeat)
-        .add_systems(Update, camera_movement)
-        .run();
-
-    token.cancel();
-}
-
-fn system_setup(mut commands: Commands) {
-    commands.spawn(Camera2dBundle {
-        transform: Transform::from_xyz(-9.1144_888 * COORDS_ZOOM, 38.756 * COORDS_ZOOM, 1.0),
-        projection: OrthographicProjection {
-            scale: 1.0 / 60.0,
-            ..default()
+    let (exit_tx, exit_rx) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            let _ = exit_tx.send(());
         }
-        .into(),
-        ..default()
     });
-}
-
-fn camera_movement(
-    keys: Res<ButtonInput<KeyCode>>,
-    mut query: Query<&mut Transform, With<Camera2d>>,
-) {
-    let mut camera_transform = query.single_mut();
 
-    let mut direction = Vec3::ZERO;
+    let mut app = App::new();
+    app.insert_resource(IOResource { sender: tx })
+        .insert_resource(config.ground_station)
+        .insert_resource(DefaultConnectionPolicy(config.connection))
+        .insert_resource(InitialDrones { count: cli.drones })
+        .insert_resource(SimulationClock::new(cli.paused, cli.time_scale))
+        .insert_resource(Time::<Fixed>::from_hz(cli.tick_rate))
+        .insert_resource(SimulationRng::seeded(seed))
+        .insert_resource(ExitSignal { receiver: exit_rx });
 
-    if keys.pressed(KeyCode::KeyW) {
-        direction.y += 1.0;
-    }
-    if keys.pressed(KeyCode::KeyS) {
-        direction.y -= 1.0;
+    if let Some(scenario) = scenario {
+        app.insert_resource(scenario);
     }
-    if keys.pressed(KeyCode::KeyA) {
-        direction.x -= 1.0;
-    }
-    if keys.pressed(KeyCode::KeyD) {
-        direction.x += 1.0;
+
+    if cli.headless {
+        app.add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(
+            Duration::from_secs_f64(1.0 / HEADLESS_FRAME_RATE),
+        )));
+    } else {
+        app.add_plugins(DefaultPlugins).add_plugins(UiPlugin);
     }
 
-    let speed = 25.0;
-    camera_transform.translation += direction * speed * 0.01;
+    app.add_plugins(SimulationPlugin).run();
+
+    // Dropping the world closes every connection's channel, letting the IO
+    // tasks finish the `Unregister` handshakes queued on exit
+    drop(app);
+    tracker.close();
+    let _ = tokio::time::timeout(SHUTDOWN_TIMEOUT, tracker.wait()).await;
+
+    token.cancel();
 }
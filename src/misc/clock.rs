@@ -0,0 +1,94 @@
+use bevy::prelude::*;
+
+pub const MIN_TIME_SCALE: f64 = 0.1;
+pub const MAX_TIME_SCALE: f64 = 50.0;
+
+/// `FixedUpdate` ticks per simulated second unless `--tick-rate` says
+/// otherwise.
+pub const DEFAULT_TICK_RATE: f64 = 60.0;
+
+/// State of the simulation clock. Everything that simulates (movement,
+/// batteries, heartbeats, mission updates) runs in `FixedUpdate`, which
+/// Bevy drives from virtual time, and `system_drive_virtual_time` keeps
+/// virtual time in line with this resource. Networking timeouts stay on
+/// real time.
+#[derive(Debug, Resource)]
+pub struct SimulationClock {
+    pub paused: bool,
+    /// Simulated seconds per real second.
+    pub time_scale: f64,
+    pending_steps: u32,
+}
+
+impl Default for SimulationClock {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            time_scale: 1.0,
+            pending_steps: 0,
+        }
+    }
+}
+
+impl SimulationClock {
+    pub fn new(paused: bool, time_scale: f64) -> Self {
+        Self {
+            paused,
+            time_scale: time_scale.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE),
+            pending_steps: 0,
+        }
+    }
+}
+
+/// Changes the simulation clock. Sent by the UI, anything else can too.
+#[derive(Clone, Copy, Debug, PartialEq, Event)]
+pub enum ClockControl {
+    Pause,
+    Resume,
+    /// Advances a paused clock by exactly one `FixedUpdate` tick.
+    Step,
+    SetTimeScale(f64),
+}
+
+pub fn system_apply_clock_controls(
+    mut controls: EventReader<ClockControl>,
+    mut clock: ResMut<SimulationClock>,
+) {
+    for control in controls.read() {
+        match *control {
+            ClockControl::Pause => clock.paused = true,
+            ClockControl::Resume => clock.paused = false,
+            ClockControl::Step if clock.paused => clock.pending_steps += 1,
+            ClockControl::Step => {}
+            ClockControl::SetTimeScale(scale) => {
+                clock.time_scale = scale.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE);
+            }
+        }
+    }
+}
+
+/// Runs right after Bevy advances its clocks, so a requested step is picked
+/// up by this frame's `FixedUpdate`.
+pub fn system_drive_virtual_time(
+    mut clock: ResMut<SimulationClock>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+    mut time: ResMut<Time>,
+    fixed_time: Res<Time<Fixed>>,
+) {
+    if virtual_time.relative_speed_f64() != clock.time_scale {
+        virtual_time.set_relative_speed_f64(clock.time_scale);
+    }
+
+    if !clock.paused {
+        clock.pending_steps = 0;
+        virtual_time.unpause();
+        return;
+    }
+
+    virtual_time.pause();
+    if clock.pending_steps > 0 {
+        clock.pending_steps -= 1;
+        virtual_time.advance_by(fixed_time.timestep());
+        *time = virtual_time.as_generic();
+    }
+}
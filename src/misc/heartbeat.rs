@@ -1,43 +1,64 @@
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
 use bevy::prelude::*;
 
 use crate::{
-    domain::{connection::Connection, drone::Drone},
-    mavlink::dialects::serpe_dialect::messages::Heartbeat,
+    domain::{
+        battery::Battery,
+        connection::{Connection, IncomingMessage},
+        drone::Drone,
+        faults::{gps_position, Faults},
+        telemetry::Telemetry,
+    },
+    io::wire,
+    mavlink::dialects::SerpeDialect,
+    misc::rng::DroneRng,
 };
 
-#[derive(Resource)]
-pub struct HeartbeatTimer {
-    last_time: Instant,
-}
-
-impl Default for HeartbeatTimer {
-    fn default() -> Self {
-        HeartbeatTimer {
-            last_time: Instant::now(),
+/// Sends each connected drone's heartbeat at the rate set by its `Telemetry`.
+#[allow(clippy::type_complexity)]
+pub fn system_heartbeat(
+    time: Res<Time>,
+    mut connection_query: Query<(
+        &Drone,
+        &mut Connection,
+        &mut Telemetry,
+        &mut DroneRng,
+        Option<&Battery>,
+        Option<&Faults>,
+    )>,
+) {
+    // Link health measures the network, so it stays on real time
+    let now = Instant::now();
+    for (drone, mut connection, mut telemetry, mut rng, battery, faults) in
+        connection_query.iter_mut()
+    {
+        if !telemetry.heartbeat_due(time.delta(), &mut rng.heartbeat) {
+            continue;
         }
+
+        connection.health.heartbeat_sent(now);
+        let _ = connection
+            .sender
+            .try_send(SerpeDialect::Heartbeat(wire::heartbeat(
+                &gps_position(drone, faults),
+                battery,
+            )));
     }
 }
 
-pub fn system_heartbeat(
-    mut heartbeat_timer: ResMut<HeartbeatTimer>,
-    mut connection_query: Query<(&Drone, &mut Connection)>,
+pub fn system_heartbeat_ack(
+    mut incoming_messages: EventReader<IncomingMessage>,
+    mut connection_query: Query<&mut Connection>,
 ) {
-    let current_time = Instant::now();
-
-    if current_time.duration_since(heartbeat_timer.last_time) >= Duration::from_secs(1) {
-        for (drone, connection) in connection_query.iter_mut() {
-            let _ = connection
-                .sender
-                .try_send(crate::mavlink::dialects::SerpeDialect::Heartbeat(
-                    Heartbeat {
-                        latitude: drone.coordinates.latitude,
-                        longitude: drone.coordinates.longitude,
-                    },
-                ));
+    let now = Instant::now();
+    for event in incoming_messages.read() {
+        if !matches!(event.message, SerpeDialect::HeartbeatAck(_)) {
+            continue;
         }
 
-        heartbeat_timer.last_time = current_time;
+        if let Ok(mut connection) = connection_query.get_mut(event.entity) {
+            connection.health.ack_received(now);
+        }
     }
 }
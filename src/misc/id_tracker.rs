@@ -10,4 +10,9 @@ impl DroneIdTracker {
         self.next_id += 1;
         self.next_id
     }
+
+    /// Makes sure `increment` never hands out `agent_id` or anything below it.
+    pub fn reserve(&mut self, agent_id: u32) {
+        self.next_id = self.next_id.max(agent_id);
+    }
 }
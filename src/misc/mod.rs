@@ -1,3 +1,5 @@
+pub mod clock;
 pub mod heartbeat;
 pub mod id_tracker;
+pub mod rng;
 pub mod selected_drone;
@@ -0,0 +1,81 @@
+use bevy::prelude::*;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::domain::drone::Drone;
+
+/// Puts the drone streams above the ones `link_rng` hands out.
+const DRONE_STREAMS: u64 = 1 << 40;
+
+/// The only source of randomness for the simulation, drones and links draw
+/// from streams of their own seeded from it. ChaCha gives the same sequence
+/// on every platform, so a run can be reproduced from its seed.
+#[derive(Resource)]
+pub struct SimulationRng {
+    seed: u64,
+    rng: ChaCha8Rng,
+}
+
+impl SimulationRng {
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn rng(&mut self) -> &mut ChaCha8Rng {
+        &mut self.rng
+    }
+
+    /// Stream number `purpose` of the drone `agent_id`, see `DroneRng`.
+    fn drone_stream(&self, agent_id: u32, purpose: u64) -> ChaCha8Rng {
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        rng.set_stream((DRONE_STREAMS * purpose) | agent_id as u64);
+        rng
+    }
+}
+
+impl Default for SimulationRng {
+    /// Seeded from the OS, use `seed()` to repeat the run.
+    fn default() -> Self {
+        Self::seeded(rand::random())
+    }
+}
+
+/// A drone's own draws, one stream per purpose and all taken from the
+/// simulation seed. What a drone draws then doesn't depend on the other
+/// drones, on the order systems run in, or on whether it is connected.
+#[derive(Component)]
+pub struct DroneRng {
+    pub heartbeat: ChaCha8Rng,
+    pub mission_update: ChaCha8Rng,
+    pub faults: ChaCha8Rng,
+}
+
+impl DroneRng {
+    pub fn new(rng: &SimulationRng, agent_id: u32) -> Self {
+        Self {
+            heartbeat: rng.drone_stream(agent_id, 1),
+            mission_update: rng.drone_stream(agent_id, 2),
+            faults: rng.drone_stream(agent_id, 3),
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn system_attach_drone_rng(
+    mut commands: Commands,
+    rng: Res<SimulationRng>,
+    new_drones_query: Query<(Entity, &Drone), (Added<Drone>, Without<DroneRng>)>,
+) {
+    for (entity, drone) in new_drones_query.iter() {
+        commands
+            .entity(entity)
+            .insert(DroneRng::new(&rng, drone.agent_id));
+    }
+}
@@ -1,6 +1,16 @@
+use std::collections::HashSet;
+
 use bevy::prelude::*;
 
 #[derive(Default, Resource)]
 pub struct SelectedDrone {
     pub entity: Option<Entity>,
 }
+
+/// Drones ticked in the drone list, the target of bulk actions.
+#[derive(Default, Resource)]
+pub struct SelectedDrones {
+    pub entities: HashSet<Entity>,
+    /// Last drone ticked without shift, where shift-click ranges start.
+    pub anchor: Option<Entity>,
+}
@@ -0,0 +1,531 @@
+use std::{collections::HashSet, net::SocketAddr, path::Path};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    domain::{
+        battery::Battery,
+        coordinates::{Coordinates, DEFAULT_COORDINATES},
+        drone::{AutoConnect, Drone, DroneState},
+        faults::{Fault, Faults},
+        telemetry::Telemetry,
+    },
+    io::{
+        impairment::Impairment,
+        protocol::ProtocolConfig,
+        transport::{TransportConfig, DEFAULT_SERVER_ADDRESS},
+    },
+    misc::id_tracker::DroneIdTracker,
+};
+
+/// Drones to spawn at startup, loaded from the file given with `--scenario`.
+#[derive(Debug, Default, Deserialize, Resource)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    /// Seed for the simulation's random choices, `--seed` overrides it.
+    pub seed: Option<u64>,
+    /// Has the ground station dial in to drones without a transport of
+    /// their own.
+    pub server: Option<ServerSpec>,
+    #[serde(rename = "drone")]
+    pub drones: Vec<DroneSpec>,
+}
+
+/// One `[[drone]]` entry. Anything left out gets the same default as a drone
+/// created from the UI.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DroneSpec {
+    /// Defaults to the next free id.
+    pub agent_id: Option<u32>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// In metres per second.
+    pub cruise_speed: Option<f64>,
+    pub battery: Option<BatterySpec>,
+    pub telemetry: Option<TelemetrySpec>,
+    /// Starts the drone on a flaky link, see `Impairment`.
+    pub impairment: Impairment,
+    #[serde(rename = "fault")]
+    pub faults: Vec<FaultSpec>,
+    /// Turn the drone on.
+    pub online: bool,
+    /// Connect to the ground station as soon as possible. Implies `online`.
+    pub connect: bool,
+    /// Overrides the global ground-station address for this drone.
+    pub ground_station: Option<String>,
+    /// Defaults to the scenario's `[server]`, or else a TCP client. See
+    /// `TransportConfig`.
+    pub transport: Option<TransportConfig>,
+    /// MAVLink version and signing, see `ProtocolConfig`.
+    pub protocol: ProtocolConfig,
+}
+
+/// `[server]`, a `tcp_server` transport for every drone that doesn't pick
+/// one.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerSpec {
+    /// `ip:port` of the shared port, or of the first drone's.
+    #[serde(default = "default_server_address")]
+    pub address: String,
+    /// All drones on one port, told apart by system id. Otherwise drone `n`
+    /// in the list listens on the port `n` above `address`'s.
+    #[serde(default)]
+    pub shared: bool,
+}
+
+fn default_server_address() -> String {
+    DEFAULT_SERVER_ADDRESS.to_string()
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatterySpec {
+    /// In Wh.
+    pub capacity: Option<f64>,
+    /// In Wh, defaults to a full battery.
+    pub charge: Option<f64>,
+    /// Fraction of the capacity, between 0 and 1.
+    pub low_threshold: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetrySpec {
+    /// In seconds.
+    pub heartbeat_interval: Option<f64>,
+    /// In seconds.
+    pub mission_update_interval: Option<f64>,
+    /// Largest random change to each interval, in seconds.
+    pub jitter: Option<f64>,
+}
+
+/// One `[[drone.fault]]` entry, injected on a schedule.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FaultSpec {
+    pub kind: Fault,
+    /// Simulated seconds after startup.
+    pub at: f64,
+    /// When to clear the fault again, if ever. Not allowed on one-shot faults.
+    pub until: Option<f64>,
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+
+        let scenario: Self = toml::from_str(&contents)
+            .map_err(|err| format!("invalid {}: {}", path.display(), err))?;
+        scenario
+            .validate()
+            .map_err(|err| format!("invalid {}: {}", path.display(), err))?;
+
+        Ok(scenario)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let mut agent_ids = HashSet::new();
+
+        if let Some(server) = &self.server {
+            server.validate(self.drones.len())?;
+        }
+
+        for (index, spec) in self.drones.iter().enumerate() {
+            if let Some(agent_id) = spec.agent_id {
+                if !agent_ids.insert(agent_id) {
+                    return Err(format!("drone {}: duplicate agent_id {}", index, agent_id));
+                }
+            }
+
+            if let Some(speed) = spec.cruise_speed {
+                if !speed.is_finite() || speed <= 0.0 {
+                    return Err(format!("drone {}: cruise_speed must be positive", index));
+                }
+            }
+
+            if let Some(latitude) = spec.latitude {
+                if !(-90.0..=90.0).contains(&latitude) {
+                    return Err(format!(
+                        "drone {}: latitude must be between -90 and 90",
+                        index
+                    ));
+                }
+            }
+
+            if let Some(longitude) = spec.longitude {
+                if !(-180.0..=180.0).contains(&longitude) {
+                    return Err(format!(
+                        "drone {}: longitude must be between -180 and 180",
+                        index
+                    ));
+                }
+            }
+
+            if let Some(battery) = &spec.battery {
+                battery
+                    .validate()
+                    .map_err(|err| format!("drone {}: {}", index, err))?;
+            }
+
+            if let Some(telemetry) = &spec.telemetry {
+                telemetry
+                    .validate()
+                    .map_err(|err| format!("drone {}: {}", index, err))?;
+            }
+
+            for fault in &spec.faults {
+                fault
+                    .validate()
+                    .map_err(|err| format!("drone {}: {}", index, err))?;
+            }
+
+            spec.impairment
+                .validate()
+                .map_err(|err| format!("drone {}: impairment {}", index, err))?;
+
+            spec.protocol
+                .validate()
+                .map_err(|err| format!("drone {}: {}", index, err))?;
+        }
+
+        Ok(())
+    }
+
+    /// The transport of the drone at `index`: its own, or the one `[server]`
+    /// gives it.
+    fn transport_for(&self, index: usize) -> TransportConfig {
+        match (&self.drones[index].transport, &self.server) {
+            (Some(transport), _) => transport.clone(),
+            (None, Some(server)) => server.transport(index),
+            (None, None) => TransportConfig::default(),
+        }
+    }
+}
+
+impl ServerSpec {
+    fn validate(&self, drones: usize) -> Result<(), String> {
+        let address = self.socket_address()?;
+        if self.shared {
+            return Ok(());
+        }
+        if address.port() == 0 {
+            return Err("server needs a fixed port unless shared".to_string());
+        }
+        if address.port() as usize + drones > u16::MAX as usize + 1 {
+            return Err(format!(
+                "server ports {} and up don't fit {} drones",
+                address.port(),
+                drones
+            ));
+        }
+        Ok(())
+    }
+
+    fn socket_address(&self) -> Result<SocketAddr, String> {
+        self.address
+            .parse()
+            .map_err(|_| format!("server address {} is not ip:port", self.address))
+    }
+
+    fn transport(&self, index: usize) -> TransportConfig {
+        let mut address = self.socket_address().expect("validated on load");
+        if !self.shared {
+            address.set_port(address.port() + index as u16);
+        }
+        TransportConfig::TcpServer {
+            address: address.to_string(),
+            shared: self.shared,
+        }
+    }
+}
+
+impl BatterySpec {
+    fn validate(&self) -> Result<(), String> {
+        let capacity = self.capacity.unwrap_or(Battery::default().capacity);
+        if !capacity.is_finite() || capacity <= 0.0 {
+            return Err("battery capacity must be positive".to_string());
+        }
+        if let Some(charge) = self.charge {
+            if !(0.0..=capacity).contains(&charge) {
+                return Err(format!("battery charge must be between 0 and {}", capacity));
+            }
+        }
+        if let Some(threshold) = self.low_threshold {
+            if !(0.0..=1.0).contains(&threshold) {
+                return Err("battery low_threshold must be between 0 and 1".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn build(&self) -> Battery {
+        let mut battery = Battery::default();
+        if let Some(capacity) = self.capacity {
+            battery.capacity = capacity;
+        }
+        battery.charge = self.charge.unwrap_or(battery.capacity);
+        if let Some(threshold) = self.low_threshold {
+            battery.low_threshold = threshold;
+        }
+        battery
+    }
+}
+
+impl TelemetrySpec {
+    fn validate(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("heartbeat_interval", self.heartbeat_interval),
+            ("mission_update_interval", self.mission_update_interval),
+        ] {
+            if let Some(value) = value {
+                if !value.is_finite() || value <= 0.0 {
+                    return Err(format!("telemetry {} must be positive", name));
+                }
+            }
+        }
+        if let Some(jitter) = self.jitter {
+            if !jitter.is_finite() || jitter < 0.0 {
+                return Err("telemetry jitter must be zero or more".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn build(&self) -> Telemetry {
+        let defaults = Telemetry::default();
+        Telemetry::new(
+            self.heartbeat_interval
+                .unwrap_or(defaults.heartbeat_interval),
+            self.mission_update_interval
+                .unwrap_or(defaults.mission_update_interval),
+            self.jitter.unwrap_or(defaults.jitter),
+        )
+    }
+}
+
+impl FaultSpec {
+    fn validate(&self) -> Result<(), String> {
+        if !self.at.is_finite() || self.at < 0.0 {
+            return Err(format!("fault {} can't start before 0", self.kind));
+        }
+        match self.until {
+            Some(until) if !until.is_finite() => {
+                Err(format!("fault {} must end at a finite time", self.kind))
+            }
+            Some(_) if self.kind.is_one_shot() => {
+                Err(format!("fault {} happens once and has no until", self.kind))
+            }
+            Some(until) if until <= self.at => {
+                Err(format!("fault {} must end after it starts", self.kind))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl DroneSpec {
+    fn build(
+        &self,
+        agent_id: u32,
+        transport: TransportConfig,
+    ) -> (Drone, Battery, Telemetry, Impairment, Faults) {
+        let coordinates = Coordinates {
+            latitude: self.latitude.unwrap_or(DEFAULT_COORDINATES.latitude),
+            longitude: self.longitude.unwrap_or(DEFAULT_COORDINATES.longitude),
+            ..DEFAULT_COORDINATES
+        };
+
+        let mut drone = Drone::new(agent_id, coordinates);
+        if self.online || self.connect {
+            drone.state = DroneState::Online;
+        }
+        if let Some(speed) = self.cruise_speed {
+            drone.cruise_speed = speed;
+        }
+        drone.ground_station = self.ground_station.clone();
+        drone.transport = transport;
+        drone.protocol = self.protocol.clone();
+
+        let battery = self
+            .battery
+            .as_ref()
+            .map(BatterySpec::build)
+            .unwrap_or_default();
+        let telemetry = self
+            .telemetry
+            .as_ref()
+            .map(TelemetrySpec::build)
+            .unwrap_or_default();
+
+        let mut faults = Faults::default();
+        for fault in &self.faults {
+            faults.schedule(fault.kind, true, fault.at);
+            if let Some(until) = fault.until {
+                faults.schedule(fault.kind, false, until);
+            }
+        }
+
+        (drone, battery, telemetry, self.impairment.clone(), faults)
+    }
+}
+
+pub fn system_spawn_scenario(
+    mut commands: Commands,
+    mut id_tracker: ResMut<DroneIdTracker>,
+    scenario: Option<Res<Scenario>>,
+) {
+    let Some(scenario) = scenario else {
+        return;
+    };
+
+    // Explicit ids go first so the generated ones can't collide with them
+    for agent_id in scenario.drones.iter().filter_map(|spec| spec.agent_id) {
+        id_tracker.reserve(agent_id);
+    }
+
+    for (index, spec) in scenario.drones.iter().enumerate() {
+        let agent_id = spec.agent_id.unwrap_or_else(|| id_tracker.increment());
+        let transport = scenario.transport_for(index);
+        let mut entity_commands = commands.spawn(spec.build(agent_id, transport));
+        if spec.connect {
+            entity_commands.insert(AutoConnect);
+        }
+    }
+
+    println!("Spawned {} drones from scenario", scenario.drones.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(toml: &str) -> Result<(), String> {
+        let scenario: Scenario = toml::from_str(toml).expect("scenario should parse");
+        scenario.validate()
+    }
+
+    #[test]
+    fn accepts_a_full_drone() {
+        let result = validate(
+            r#"
+            [[drone]]
+            agent_id = 1
+            latitude = 45.0
+            longitude = -180.0
+            cruise_speed = 12.5
+            battery = { capacity = 50.0, charge = 25.0, low_threshold = 0.2 }
+
+            [[drone.fault]]
+            kind = "gps_drift"
+            at = 0.0
+            until = 10.0
+
+            [[drone]]
+            agent_id = 2
+            "#,
+        );
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn rejects_duplicate_agent_ids() {
+        let result = validate(
+            r#"
+            [[drone]]
+            agent_id = 3
+            [[drone]]
+            agent_id = 3
+            "#,
+        );
+        assert_eq!(result, Err("drone 1: duplicate agent_id 3".to_string()));
+    }
+
+    #[test]
+    fn rejects_positions_out_of_range() {
+        for drone in [
+            "latitude = 90.5",
+            "latitude = -91.0",
+            "latitude = nan",
+            "longitude = 180.5",
+            "longitude = -inf",
+        ] {
+            let result = validate(&format!("[[drone]]\n{}", drone));
+            assert!(result.is_err(), "{} was accepted", drone);
+        }
+    }
+
+    #[test]
+    fn rejects_a_cruise_speed_that_is_not_positive() {
+        for speed in ["0.0", "-1.0", "nan", "inf"] {
+            let result = validate(&format!("[[drone]]\ncruise_speed = {}", speed));
+            assert!(result.is_err(), "cruise_speed = {} was accepted", speed);
+        }
+    }
+
+    #[test]
+    fn rejects_a_battery_capacity_that_is_not_positive() {
+        for capacity in ["0.0", "-5.0", "nan", "inf"] {
+            let result = validate(&format!(
+                "[[drone]]\nbattery = {{ capacity = {} }}",
+                capacity
+            ));
+            assert!(result.is_err(), "capacity = {} was accepted", capacity);
+        }
+    }
+
+    #[test]
+    fn rejects_fault_times_that_are_not_finite() {
+        for times in [
+            "at = nan",
+            "at = inf",
+            "at = -1.0",
+            "at = 1.0\nuntil = nan",
+            "at = 1.0\nuntil = inf",
+            "at = 5.0\nuntil = 5.0",
+        ] {
+            let result = validate(&format!(
+                "[[drone]]\n[[drone.fault]]\nkind = \"gps_freeze\"\n{}",
+                times
+            ));
+            assert!(result.is_err(), "{} was accepted", times);
+        }
+    }
+
+    #[test]
+    fn rejects_telemetry_timings_that_are_not_finite() {
+        for telemetry in [
+            "heartbeat_interval = 0.0",
+            "heartbeat_interval = nan",
+            "heartbeat_interval = inf",
+            "mission_update_interval = nan",
+            "mission_update_interval = inf",
+            "jitter = -0.1",
+            "jitter = nan",
+            "jitter = inf",
+        ] {
+            let result = validate(&format!("[[drone]]\ntelemetry = {{ {} }}", telemetry));
+            assert!(result.is_err(), "{} was accepted", telemetry);
+        }
+    }
+
+    #[test]
+    fn rejects_an_end_on_a_one_shot_fault() {
+        let result = validate(
+            r#"
+            [[drone]]
+            [[drone.fault]]
+            kind = "power_loss"
+            at = 1.0
+            until = 2.0
+            "#,
+        );
+        assert_eq!(
+            result,
+            Err("drone 0: fault Power Loss happens once and has no until".to_string())
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use bevy::{prelude::*, time::TimeSystem};
+
+use crate::{
+    domain::{
+        actions::{system_apply_drone_actions, DroneActionRequested},
+        battery::{system_attach_battery, system_battery_failsafe, system_drain_battery},
+        connection::{
+            system_apply_impairment, system_attach_connection_policy, system_attach_impairment,
+            system_auto_connect, system_disconnect, system_handle_lost_connections,
+            system_link_health, system_log_connection_errors, system_log_disconnections,
+            system_poll_connection_errors, system_poll_pending_connections,
+            system_receive_messages, system_reconnect, system_unregister_on_exit, ConnectionError,
+            DefaultConnectionPolicy, DroneDisconnected, IncomingMessage,
+        },
+        coordinates::DEFAULT_COORDINATES,
+        drone::{AutoConnect, Drone, DroneState},
+        faults::{
+            system_apply_faults, system_apply_tampering, system_attach_faults, system_tick_faults,
+            FaultRequested,
+        },
+        mission::{
+            system_mission_update_coordinates, system_mission_update_sender,
+            system_mission_updater, system_mission_upload_timeout, system_resend_mission_finished,
+        },
+        telemetry::system_attach_telemetry,
+    },
+    misc::{
+        clock::{
+            system_apply_clock_controls, system_drive_virtual_time, ClockControl, SimulationClock,
+        },
+        heartbeat::{system_heartbeat, system_heartbeat_ack},
+        id_tracker::DroneIdTracker,
+        rng::{system_attach_drone_rng, SimulationRng},
+    },
+    scenario::system_spawn_scenario,
+};
+
+/// Fires once when the process receives Ctrl-C, so the app can shut down
+/// through `AppExit` and unregister its drones.
+#[derive(Resource)]
+pub struct ExitSignal {
+    pub receiver: tokio::sync::oneshot::Receiver<()>,
+}
+
+/// Number of drones spawned by `system_spawn_initial_drones`.
+#[derive(Default, Resource)]
+pub struct InitialDrones {
+    pub count: u32,
+}
+
+/// Everything needed to run drones against a ground station, without any
+/// rendering. Shared by the windowed and headless modes.
+pub struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(DroneIdTracker::default())
+            .init_resource::<InitialDrones>()
+            .init_resource::<SimulationClock>()
+            .init_resource::<SimulationRng>()
+            .init_resource::<DefaultConnectionPolicy>()
+            .add_event::<ConnectionError>()
+            .add_event::<IncomingMessage>()
+            .add_event::<DroneDisconnected>()
+            .add_event::<DroneActionRequested>()
+            .add_event::<FaultRequested>()
+            .add_event::<ClockControl>()
+            .add_systems(First, system_drive_virtual_time.after(TimeSystem))
+            .add_systems(Update, system_apply_clock_controls)
+            .add_systems(
+                Startup,
+                (system_spawn_scenario, system_spawn_initial_drones).chain(),
+            )
+            .add_systems(
+                Update,
+                (
+                    system_attach_connection_policy,
+                    system_apply_drone_actions,
+                    system_apply_faults,
+                    system_auto_connect,
+                    system_poll_pending_connections,
+                    system_receive_messages,
+                    system_poll_connection_errors,
+                    system_disconnect,
+                    system_handle_lost_connections,
+                    system_reconnect,
+                )
+                    .chain(),
+            )
+            .add_systems(Update, system_log_connection_errors)
+            .add_systems(Update, system_log_disconnections)
+            .add_systems(Update, system_exit_on_signal)
+            .add_systems(Last, system_unregister_on_exit)
+            .add_systems(
+                Update,
+                system_mission_updater.after(system_receive_messages),
+            )
+            .add_systems(
+                Update,
+                (
+                    system_attach_battery,
+                    system_attach_telemetry,
+                    system_attach_impairment,
+                    system_attach_faults,
+                    system_attach_drone_rng,
+                ),
+            )
+            .add_systems(
+                Update,
+                (
+                    system_apply_impairment,
+                    system_apply_tampering,
+                    system_resend_mission_finished,
+                )
+                    .after(system_poll_pending_connections),
+            )
+            // Everything that integrates time runs at a fixed rate so a run
+            // doesn't depend on the frame rate, and in a fixed order so it
+            // repeats with the same seed
+            .add_systems(
+                FixedUpdate,
+                (
+                    system_tick_faults,
+                    system_mission_update_coordinates,
+                    system_drain_battery,
+                    system_battery_failsafe,
+                    system_mission_update_sender,
+                    system_heartbeat,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                Update,
+                system_mission_upload_timeout.after(system_mission_updater),
+            )
+            .add_systems(
+                Update,
+                (system_heartbeat_ack, system_link_health)
+                    .chain()
+                    .after(system_receive_messages),
+            );
+    }
+}
+
+fn system_exit_on_signal(
+    exit_signal: Option<ResMut<ExitSignal>>,
+    mut exit_events: EventWriter<AppExit>,
+) {
+    if let Some(mut exit_signal) = exit_signal {
+        if exit_signal.receiver.try_recv().is_ok() {
+            exit_events.send(AppExit::Success);
+        }
+    }
+}
+
+fn system_spawn_initial_drones(
+    mut commands: Commands,
+    mut id_tracker: ResMut<DroneIdTracker>,
+    initial_drones: Res<InitialDrones>,
+) {
+    for _ in 0..initial_drones.count {
+        let mut drone = Drone::new(id_tracker.increment(), DEFAULT_COORDINATES);
+        drone.state = DroneState::Online;
+        commands.spawn((drone, AutoConnect));
+    }
+}
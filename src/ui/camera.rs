@@ -0,0 +1,41 @@
+use bevy::{input::keyboard::KeyCode, prelude::*};
+
+use crate::domain::coordinates::DEFAULT_COORDINATES;
+
+use super::world_position;
+
+pub fn system_setup_camera(mut commands: Commands) {
+    commands.spawn(Camera2dBundle {
+        transform: Transform::from_translation(world_position(&DEFAULT_COORDINATES).extend(1.0)),
+        projection: OrthographicProjection {
+            scale: 1.0 / 60.0,
+            ..default()
+        },
+        ..default()
+    });
+}
+
+pub fn system_camera_movement(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut query: Query<&mut Transform, With<Camera2d>>,
+) {
+    let mut camera_transform = query.single_mut();
+
+    let mut direction = Vec3::ZERO;
+
+    if keys.pressed(KeyCode::KeyW) {
+        direction.y += 1.0;
+    }
+    if keys.pressed(KeyCode::KeyS) {
+        direction.y -= 1.0;
+    }
+    if keys.pressed(KeyCode::KeyA) {
+        direction.x -= 1.0;
+    }
+    if keys.pressed(KeyCode::KeyD) {
+        direction.x += 1.0;
+    }
+
+    let speed = 25.0;
+    camera_transform.translation += direction * speed * 0.01;
+}
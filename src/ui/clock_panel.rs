@@ -0,0 +1,59 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::misc::clock::{ClockControl, SimulationClock, MAX_TIME_SCALE, MIN_TIME_SCALE};
+
+pub fn show_clock_panel(
+    contexts: &mut EguiContexts,
+    clock: &SimulationClock,
+    virtual_time: &Time<Virtual>,
+    controls: &mut EventWriter<ClockControl>,
+) {
+    egui::TopBottomPanel::top("simulation_clock").show(contexts.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "Simulation Time: {}",
+                format_elapsed(virtual_time.elapsed_seconds_f64())
+            ));
+            ui.separator();
+
+            if clock.paused {
+                if ui.button("Resume").clicked() {
+                    controls.send(ClockControl::Resume);
+                }
+            } else if ui.button("Pause").clicked() {
+                controls.send(ClockControl::Pause);
+            }
+
+            if ui
+                .add_enabled(clock.paused, egui::Button::new("Step"))
+                .clicked()
+            {
+                controls.send(ClockControl::Step);
+            }
+            ui.separator();
+
+            let mut time_scale = clock.time_scale;
+            let slider = egui::Slider::new(&mut time_scale, MIN_TIME_SCALE..=MAX_TIME_SCALE)
+                .logarithmic(true)
+                .text("Speed")
+                .suffix("x");
+            if ui.add(slider).changed() {
+                controls.send(ClockControl::SetTimeScale(time_scale));
+            }
+            if ui.button("1x").clicked() {
+                controls.send(ClockControl::SetTimeScale(1.0));
+            }
+        });
+    });
+}
+
+fn format_elapsed(seconds: f64) -> String {
+    let total = seconds as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        total / 3600,
+        (total / 60) % 60,
+        total % 60
+    )
+}
@@ -1,106 +1,350 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_egui::{egui, EguiContexts};
+use rand::Rng;
+
 use crate::{
     domain::{
-        coordinates::Coordinates,
-        drone::{Drone, DroneState},
+        actions::{DroneAction, DroneActionRequested},
+        coordinates::{Coordinates, DEFAULT_COORDINATES},
+        drone::{AutoConnect, Drone, DroneState},
+        formation::Formation,
+    },
+    misc::{
+        id_tracker::DroneIdTracker,
+        rng::SimulationRng,
+        selected_drone::{SelectedDrone, SelectedDrones},
     },
-    misc::{id_tracker::DroneIdTracker, selected_drone::SelectedDrone},
 };
-use bevy::prelude::*;
-use bevy_egui::{egui, EguiContexts};
 
-pub fn show_left_panel(
-    commands: &mut Commands,
-    contexts: &mut EguiContexts,
-    id_tracker: &mut ResMut<DroneIdTracker>,
-    selected_drone: &mut ResMut<SelectedDrone>,
-    drones_query: &mut Query<(Entity, &mut Drone)>,
-    asset_server: Res<AssetServer>,
-) {
+use super::coordinates_at;
+
+const COORDINATES_DRAG_SPEED: f64 = 0.00001;
+const MAX_SPAWN_COUNT: u32 = 1000;
+const DEFAULT_GRID: Formation = Formation::Grid { spacing: 50.0 };
+const DEFAULT_CIRCLE: Formation = Formation::Circle { radius: 200.0 };
+const DEFAULT_RANDOM: Formation = Formation::Random {
+    width: 500.0,
+    height: 500.0,
+};
+
+/// Settings of the "Spawn Drones" tool, kept between frames.
+#[derive(Resource)]
+pub struct SpawnTool {
+    count: u32,
+    formation: Formation,
+    turn_on: bool,
+    connect: bool,
+}
+
+impl Default for SpawnTool {
+    fn default() -> Self {
+        Self {
+            count: 10,
+            formation: DEFAULT_GRID,
+            turn_on: false,
+            connect: false,
+        }
+    }
+}
+
+/// Where "Teleport" sends the selected drones, kept between frames.
+#[derive(Resource)]
+pub struct TeleportTarget(pub Coordinates);
+
+impl Default for TeleportTarget {
+    fn default() -> Self {
+        Self(DEFAULT_COORDINATES)
+    }
+}
+
+/// Everything the left panel reads or changes.
+#[derive(SystemParam)]
+pub struct LeftPanelParams<'w, 's> {
+    commands: Commands<'w, 's>,
+    id_tracker: ResMut<'w, DroneIdTracker>,
+    selected_drone: ResMut<'w, SelectedDrone>,
+    selected_drones: ResMut<'w, SelectedDrones>,
+    spawn_tool: ResMut<'w, SpawnTool>,
+    teleport_target: ResMut<'w, TeleportTarget>,
+    drones_query: Query<'w, 's, (Entity, &'static Drone)>,
+    camera_query: Query<'w, 's, &'static Transform, With<Camera2d>>,
+    actions: EventWriter<'w, DroneActionRequested>,
+    rng: ResMut<'w, SimulationRng>,
+}
+
+impl LeftPanelParams<'_, '_> {
+    /// Every drone, sorted by agent id so the list and shift-click ranges
+    /// are stable from frame to frame.
+    fn sorted_drones(&self) -> Vec<(Entity, u32, DroneState)> {
+        let mut drones: Vec<_> = self
+            .drones_query
+            .iter()
+            .map(|(entity, drone)| (entity, drone.agent_id, drone.state))
+            .collect();
+        drones.sort_by_key(|(_, agent_id, _)| *agent_id);
+        drones
+    }
+
+    fn camera_centre(&self) -> Coordinates {
+        self.camera_query
+            .get_single()
+            .map(|camera| coordinates_at(camera.translation.truncate()))
+            .unwrap_or(DEFAULT_COORDINATES)
+    }
+
+    fn request<'a>(&mut self, entities: impl IntoIterator<Item = &'a Entity>, action: DroneAction) {
+        self.actions
+            .send_batch(entities.into_iter().map(|entity| DroneActionRequested {
+                entity: *entity,
+                action: action.clone(),
+            }));
+    }
+}
+
+pub fn show_left_panel(contexts: &mut EguiContexts, params: &mut LeftPanelParams) {
+    // Drop drones deleted since the last frame
+    let drones_query = &params.drones_query;
+    params
+        .selected_drones
+        .entities
+        .retain(|entity| drones_query.contains(*entity));
+
     egui::SidePanel::left("drone_control_panel")
         .default_width(200.0)
         .show(contexts.ctx_mut(), |ui| {
             ui.heading("Drone Control");
 
-            render_top_buttons(
-                ui,
-                commands,
-                id_tracker,
-                drones_query,
-                selected_drone,
-                asset_server,
-            );
+            render_top_buttons(ui, params);
+            ui.separator();
+            render_spawn_tool(ui, params);
             ui.separator();
-            render_drone_list(ui, drones_query, selected_drone);
+            render_bulk_actions(ui, params);
+            ui.separator();
+            render_drone_list(ui, params);
         });
 }
 
-fn render_top_buttons(
-    ui: &mut egui::Ui,
-    commands: &mut Commands,
-    id_tracker: &mut ResMut<DroneIdTracker>,
-    drones_query: &mut Query<(Entity, &mut Drone)>,
-    selected_drone: &mut ResMut<SelectedDrone>,
-    asset_server: Res<AssetServer>,
-) {
+fn render_top_buttons(ui: &mut egui::Ui, params: &mut LeftPanelParams) {
     ui.horizontal(|ui| {
         if ui.button("Create Drone").clicked() {
-            create_new_drone(commands, id_tracker, asset_server);
+            create_new_drone(&mut params.commands, &mut params.id_tracker);
         }
 
         if ui.button("Delete All Drones").clicked() {
-            delete_all_drones(commands, drones_query, selected_drone);
+            let entities: Vec<Entity> = params.drones_query.iter().map(|(e, _)| e).collect();
+            params.request(&entities, DroneAction::Delete);
+            params.selected_drone.entity = None; // Deselect any selected drone
         }
     });
 }
 
-fn create_new_drone(
-    commands: &mut Commands,
-    id_tracker: &mut ResMut<DroneIdTracker>,
-    asset_server: Res<AssetServer>,
-) {
+fn create_new_drone(commands: &mut Commands, id_tracker: &mut ResMut<DroneIdTracker>) {
     let next_id = id_tracker.increment();
-    commands
-        .spawn(Drone {
-            agent_id: next_id,
-            state: DroneState::Offline,
-            coordinates: Coordinates {
-                longitude: -9.114488884434095,
-                latitude: 38.75600095957655,
-            },
-        })
-        .insert(SpriteBundle {
-            texture: asset_server.load("drone.png"),
-            transform: Transform {
-                scale: Vec3::new(0.003, 0.003, 1.0),
-                ..Default::default()
-            },
-            ..Default::default()
+    commands.spawn(Drone::new(next_id, DEFAULT_COORDINATES));
+}
+
+fn render_spawn_tool(ui: &mut egui::Ui, params: &mut LeftPanelParams) {
+    let centre = params.camera_centre();
+    let spawn_tool = &mut *params.spawn_tool;
+
+    egui::CollapsingHeader::new("Spawn Drones").show(ui, |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Count:");
+            ui.add(egui::DragValue::new(&mut spawn_tool.count).range(1..=MAX_SPAWN_COUNT));
         });
+
+        egui::ComboBox::from_label("Layout")
+            .selected_text(spawn_tool.formation.to_string())
+            .show_ui(ui, |ui| {
+                for formation in [DEFAULT_GRID, DEFAULT_CIRCLE, DEFAULT_RANDOM] {
+                    let selected = std::mem::discriminant(&spawn_tool.formation)
+                        == std::mem::discriminant(&formation);
+                    if ui
+                        .selectable_label(selected, formation.to_string())
+                        .clicked()
+                        && !selected
+                    {
+                        spawn_tool.formation = formation;
+                    }
+                }
+            });
+
+        match &mut spawn_tool.formation {
+            Formation::Grid { spacing } => {
+                render_distance(ui, "Spacing:", spacing);
+            }
+            Formation::Circle { radius } => {
+                render_distance(ui, "Radius:", radius);
+            }
+            Formation::Random { width, height } => {
+                render_distance(ui, "Width:", width);
+                render_distance(ui, "Height:", height);
+            }
+        }
+
+        ui.checkbox(&mut spawn_tool.turn_on, "Turn On");
+        ui.add_enabled(
+            spawn_tool.turn_on,
+            egui::Checkbox::new(&mut spawn_tool.connect, "Connect"),
+        );
+
+        // Centred on whatever the camera is looking at
+        if ui.button("Spawn").clicked() {
+            spawn_drones(
+                &mut params.commands,
+                &mut params.id_tracker,
+                params.rng.rng(),
+                spawn_tool,
+                &centre,
+            );
+        }
+    });
+}
+
+fn render_distance(ui: &mut egui::Ui, label: &str, value: &mut f64) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        ui.add(
+            egui::DragValue::new(value)
+                .speed(1.0)
+                .range(1.0..=100_000.0)
+                .suffix(" m"),
+        );
+    });
 }
 
-fn delete_all_drones(
+fn spawn_drones(
     commands: &mut Commands,
-    drones_query: &mut Query<(Entity, &mut Drone)>,
-    selected_drone: &mut ResMut<SelectedDrone>,
+    id_tracker: &mut ResMut<DroneIdTracker>,
+    rng: &mut impl Rng,
+    spawn_tool: &SpawnTool,
+    centre: &Coordinates,
 ) {
-    for (entity, _) in drones_query.iter() {
-        commands.entity(entity).despawn();
+    let positions = spawn_tool
+        .formation
+        .positions(centre, spawn_tool.count as usize, rng);
+
+    for coordinates in positions {
+        let mut drone = Drone::new(id_tracker.increment(), coordinates);
+        if spawn_tool.turn_on {
+            drone.state = DroneState::Online;
+        }
+
+        let mut entity_commands = commands.spawn(drone);
+        if spawn_tool.turn_on && spawn_tool.connect {
+            entity_commands.insert(AutoConnect);
+        }
     }
-    selected_drone.entity = None; // Deselect any selected drone
 }
 
-fn render_drone_list(
-    ui: &mut egui::Ui,
-    drones_query: &mut Query<(Entity, &mut Drone)>,
-    selected_drone: &mut ResMut<SelectedDrone>,
-) {
-    egui::ScrollArea::vertical().show(ui, |ui| {
-        for (entity, drone) in drones_query.iter_mut() {
-            let drone_label = format!("Drone ID: {}, State: {}", drone.agent_id, drone.state);
+fn render_bulk_actions(ui: &mut egui::Ui, params: &mut LeftPanelParams) {
+    let selection: Vec<Entity> = params.selected_drones.entities.iter().copied().collect();
+
+    ui.horizontal(|ui| {
+        ui.label(format!("Selected: {}", selection.len()));
+        if ui.button("All").clicked() {
+            let drones_query = &params.drones_query;
+            params.selected_drones.entities = drones_query.iter().map(|(e, _)| e).collect();
+        }
+        if ui.button("None").clicked() {
+            params.selected_drones.entities.clear();
+            params.selected_drones.anchor = None;
+        }
+    });
 
-            if ui.button(&drone_label).clicked() {
-                selected_drone.entity = Some(entity); // Select the drone
+    ui.add_enabled_ui(!selection.is_empty(), |ui| {
+        ui.horizontal_wrapped(|ui| {
+            for action in [
+                DroneAction::TurnOn,
+                DroneAction::TurnOff,
+                DroneAction::Connect,
+                DroneAction::Disconnect,
+            ] {
+                if ui.button(action.to_string()).clicked() {
+                    params.request(&selection, action);
+                }
             }
+
+            if ui.button("Delete").clicked() {
+                params.request(&selection, DroneAction::Delete);
+                if let Some(entity) = params.selected_drone.entity {
+                    if selection.contains(&entity) {
+                        params.selected_drone.entity = None;
+                    }
+                }
+                params.selected_drones.entities.clear();
+                params.selected_drones.anchor = None;
+            }
+        });
+
+        ui.horizontal(|ui| {
+            let target = &mut params.teleport_target.0;
+            ui.label("Lat:");
+            ui.add(egui::DragValue::new(&mut target.latitude).speed(COORDINATES_DRAG_SPEED));
+            ui.label("Lon:");
+            ui.add(egui::DragValue::new(&mut target.longitude).speed(COORDINATES_DRAG_SPEED));
+        });
+
+        ui.horizontal(|ui| {
+            if ui.button("Use Camera Centre").clicked() {
+                params.teleport_target.0 = params.camera_centre();
+            }
+            if ui.button("Teleport").clicked() {
+                let target = params.teleport_target.0;
+                params.request(&selection, DroneAction::Teleport(target));
+            }
+        });
+    });
+}
+
+fn render_drone_list(ui: &mut egui::Ui, params: &mut LeftPanelParams) {
+    let drones = params.sorted_drones();
+    let shift = ui.input(|input| input.modifiers.shift);
+
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        for (index, &(entity, agent_id, state)) in drones.iter().enumerate() {
+            ui.horizontal(|ui| {
+                let mut checked = params.selected_drones.entities.contains(&entity);
+                if ui.checkbox(&mut checked, "").changed() {
+                    toggle_selection(&mut params.selected_drones, &drones, index, checked, shift);
+                }
+
+                let drone_label = format!("Drone ID: {}, State: {}", agent_id, state);
+                if ui.button(&drone_label).clicked() {
+                    params.selected_drone.entity = Some(entity); // Select the drone
+                }
+            });
         }
     });
 }
+
+/// Ticks or unticks the drone at `index`. With shift held, everything between
+/// the anchor and `index` follows.
+fn toggle_selection(
+    selected: &mut SelectedDrones,
+    drones: &[(Entity, u32, DroneState)],
+    index: usize,
+    checked: bool,
+    shift: bool,
+) {
+    let anchor_index = selected
+        .anchor
+        .filter(|_| shift)
+        .and_then(|anchor| drones.iter().position(|(entity, _, _)| *entity == anchor));
+
+    let range = match anchor_index {
+        Some(anchor_index) => anchor_index.min(index)..=anchor_index.max(index),
+        None => {
+            selected.anchor = Some(drones[index].0);
+            index..=index
+        }
+    };
+
+    for (entity, _, _) in &drones[range] {
+        if checked {
+            selected.entities.insert(*entity);
+        } else {
+            selected.entities.remove(entity);
+        }
+    }
+}
@@ -1,48 +1,97 @@
 use bevy::prelude::*;
-use bevy_egui::EguiContexts;
+use bevy_egui::{EguiContexts, EguiPlugin, EguiSettings};
 
 use crate::{
-    domain::{connection::Connection, drone::Drone},
-    io::IOResource,
-    misc::{id_tracker::DroneIdTracker, selected_drone::SelectedDrone},
+    domain::coordinates::Coordinates,
+    misc::{
+        clock::{ClockControl, SimulationClock},
+        selected_drone::{SelectedDrone, SelectedDrones},
+    },
 };
 
+use self::{
+    camera::{system_camera_movement, system_setup_camera},
+    left_panel::{LeftPanelParams, SpawnTool, TeleportTarget},
+    render_drones::{system_attach_drone_sprites, system_despawn_entities, system_render_drones},
+    right_panel::{FaultTool, RightPanelParams},
+};
+
+pub mod camera;
+pub mod clock_panel;
 pub mod left_panel;
 pub mod render_drones;
 pub mod right_panel;
 
-pub fn system_drone_ui_left_panel(
-    mut commands: Commands,
-    mut contexts: EguiContexts,
-    mut id_tracker: ResMut<DroneIdTracker>,
-    mut selected_drone: ResMut<SelectedDrone>,
-    mut drones_query: Query<(Entity, &mut Drone)>,
-    asset_server: Res<AssetServer>,
-) {
-    left_panel::show_left_panel(
-        &mut commands,
-        &mut contexts,
-        &mut id_tracker,
-        &mut selected_drone,
-        &mut drones_query,
-        asset_server,
-    );
+const GUI_SCALE_FACTOR: f32 = 1.5;
+
+/// World units per degree. Purely a rendering concern, nothing outside the UI
+/// should ever see scaled coordinates.
+pub const COORDS_ZOOM: f64 = 1000.0;
+
+/// Maps a position onto the 2D map (x = longitude, y = latitude).
+pub fn world_position(coordinates: &Coordinates) -> Vec2 {
+    Vec2::new(
+        (coordinates.longitude * COORDS_ZOOM) as f32,
+        (coordinates.latitude * COORDS_ZOOM) as f32,
+    )
+}
+
+/// The ground position under a point of the 2D map.
+pub fn coordinates_at(position: Vec2) -> Coordinates {
+    Coordinates {
+        latitude: position.y as f64 / COORDS_ZOOM,
+        longitude: position.x as f64 / COORDS_ZOOM,
+        altitude: 0.0,
+    }
 }
 
-pub fn system_drone_ui_right_panel(
-    mut commands: Commands,
+/// Windowed front-end: egui panels, the map camera and drone sprites.
+pub struct UiPlugin;
+
+// `EguiSettings` has more fields with bevy_egui's `open_url` feature on
+#[allow(clippy::needless_update)]
+fn egui_settings() -> EguiSettings {
+    EguiSettings {
+        scale_factor: GUI_SCALE_FACTOR,
+        ..Default::default()
+    }
+}
+
+impl Plugin for UiPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SelectedDrone::default())
+            .init_resource::<SelectedDrones>()
+            .init_resource::<SpawnTool>()
+            .init_resource::<TeleportTarget>()
+            .init_resource::<FaultTool>()
+            .insert_resource(egui_settings())
+            .add_plugins(EguiPlugin)
+            .add_systems(Startup, system_setup_camera)
+            .add_systems(
+                Update,
+                (system_clock_ui_panel, system_drone_ui_left_panel).chain(),
+            )
+            .add_systems(Update, system_drone_ui_right_panel)
+            .add_systems(Update, system_despawn_entities)
+            .add_systems(Update, system_attach_drone_sprites)
+            .add_systems(Update, system_render_drones)
+            .add_systems(Update, system_camera_movement);
+    }
+}
+
+pub fn system_clock_ui_panel(
     mut contexts: EguiContexts,
-    mut selected_drone: ResMut<SelectedDrone>,
-    mut selected_drones_query: Query<(Entity, &mut Drone, Option<&mut Connection>)>,
-    mut io_sender: ResMut<IOResource>,
-    mut camera_query: Query<&mut Transform, With<Camera2d>>,
+    clock: Res<SimulationClock>,
+    virtual_time: Res<Time<Virtual>>,
+    mut controls: EventWriter<ClockControl>,
 ) {
-    right_panel::show_right_window(
-        &mut commands,
-        &mut contexts,
-        &mut selected_drone,
-        &mut selected_drones_query,
-        &mut io_sender,
-        &mut camera_query,
-    );
+    clock_panel::show_clock_panel(&mut contexts, &clock, &virtual_time, &mut controls);
+}
+
+pub fn system_drone_ui_left_panel(mut contexts: EguiContexts, mut params: LeftPanelParams) {
+    left_panel::show_left_panel(&mut contexts, &mut params);
+}
+
+pub fn system_drone_ui_right_panel(mut contexts: EguiContexts, mut params: RightPanelParams) {
+    right_panel::show_right_window(&mut contexts, &mut params);
 }
@@ -1,22 +1,55 @@
-use bevy::{prelude::*, render::mesh::PrimitiveTopology};
+use bevy::prelude::*;
 
 use crate::{
-    domain::{coordinates::COORDS_ZOOM, drone::Drone, mission::Mission},
+    domain::{drone::Drone, faults::Faults, mission::Mission},
     misc::selected_drone::SelectedDrone,
 };
 
+use super::world_position;
+
 #[derive(Component)]
 pub struct Temporary;
 
+pub fn system_attach_drone_sprites(
+    mut commands: Commands,
+    new_drones_query: Query<Entity, Added<Drone>>,
+    asset_server: Res<AssetServer>,
+) {
+    for entity in new_drones_query.iter() {
+        commands.entity(entity).insert(SpriteBundle {
+            texture: asset_server.load("drone.png"),
+            transform: Transform {
+                scale: Vec3::new(0.003, 0.003, 1.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+    }
+}
+
+const ROUTE_COLOR: Color = Color::srgb(0.2, 0.6, 1.0);
+const FLOWN_ROUTE_COLOR: Color = Color::srgba(0.6, 0.6, 0.6, 0.6);
+const WAYPOINT_RADIUS: f32 = 0.3;
+const GPS_ERROR_COLOR: Color = Color::srgb(1.0, 0.3, 0.2);
+
+#[allow(clippy::type_complexity)]
 pub fn system_render_drones(
-    mut drones_query: Query<(Entity, &Drone, &mut Transform, Option<&Mission>)>,
+    mut drones_query: Query<(
+        Entity,
+        &Drone,
+        &mut Transform,
+        Option<&Mission>,
+        Option<&Faults>,
+    )>,
     mut commands: Commands,
+    mut gizmos: Gizmos,
     selected_drone: Res<SelectedDrone>,
     asset_server: Res<AssetServer>,
 ) {
-    for (entity, drone, mut trans, mission_opt) in drones_query.iter_mut() {
-        trans.translation.x = drone.coordinates.longitude * COORDS_ZOOM;
-        trans.translation.y = drone.coordinates.latitude * COORDS_ZOOM;
+    for (entity, drone, mut trans, mission_opt, faults_opt) in drones_query.iter_mut() {
+        let position = world_position(&drone.coordinates);
+        trans.translation.x = position.x;
+        trans.translation.y = position.y;
 
         if let Some(selected) = selected_drone.entity {
             if selected != entity {
@@ -26,27 +59,64 @@ pub fn system_render_drones(
             continue;
         }
 
+        // Where the ground station thinks the drone is, if that's elsewhere
+        if let Some(faults) = faults_opt {
+            let reported = world_position(&faults.gps_position(&drone.coordinates));
+            if reported != position {
+                gizmos.line_2d(position, reported, GPS_ERROR_COLOR);
+                gizmos.circle_2d(reported, WAYPOINT_RADIUS, GPS_ERROR_COLOR);
+            }
+        }
+
         if let Some(mission) = mission_opt {
-            let target = mission.target;
-            commands
-                .spawn(SpriteBundle {
-                    texture: asset_server.load("target.png"),
-                    transform: Transform {
-                        translation: Vec3::new(
-                            target.longitude * COORDS_ZOOM,
-                            target.latitude * COORDS_ZOOM,
-                            0.0,
-                        ),
-                        scale: Vec3::new(0.007, 0.007, 1.0),
+            render_route(&mut gizmos, position, mission);
+
+            if let Some(target) = mission.target() {
+                commands
+                    .spawn(SpriteBundle {
+                        texture: asset_server.load("target.png"),
+                        transform: Transform {
+                            translation: world_position(&target).extend(0.0),
+                            scale: Vec3::new(0.007, 0.007, 1.0),
+                            ..Default::default()
+                        },
                         ..Default::default()
-                    },
-                    ..Default::default()
-                })
-                .insert(Temporary);
+                    })
+                    .insert(Temporary);
+            }
         }
     }
 }
 
+/// Draws every waypoint of `mission`: legs already flown are greyed out, the
+/// rest of the route starts at the drone's current position.
+fn render_route(gizmos: &mut Gizmos, drone_position: Vec2, mission: &Mission) {
+    let (flown, remaining) = mission
+        .waypoints
+        .split_at(mission.current_waypoint.min(mission.waypoints.len()));
+
+    gizmos.linestrip_2d(flown.iter().map(world_position), FLOWN_ROUTE_COLOR);
+    if let Some(last_flown) = flown.last() {
+        gizmos.line_2d(
+            world_position(last_flown),
+            drone_position,
+            FLOWN_ROUTE_COLOR,
+        );
+    }
+
+    gizmos.linestrip_2d(
+        std::iter::once(drone_position).chain(remaining.iter().map(world_position)),
+        ROUTE_COLOR,
+    );
+
+    for waypoint in flown {
+        gizmos.circle_2d(world_position(waypoint), WAYPOINT_RADIUS, FLOWN_ROUTE_COLOR);
+    }
+    for waypoint in remaining {
+        gizmos.circle_2d(world_position(waypoint), WAYPOINT_RADIUS, ROUTE_COLOR);
+    }
+}
+
 pub fn system_despawn_entities(mut commands: Commands, query: Query<Entity, With<Temporary>>) {
     for entity in query.iter() {
         commands.entity(entity).despawn();
@@ -1,54 +1,89 @@
-use std::time::Duration;
-
 use crate::{
+    config::GroundStationConfig,
     domain::{
-        connection::Connection,
-        coordinates::{Coordinates, COORDS_ZOOM},
-        drone::{ConnectionState, Drone, DroneState},
+        actions::{DroneAction, DroneActionRequested},
+        battery::Battery,
+        connection::{Connection, ConnectionStatus, LinkHealth},
+        coordinates::Coordinates,
+        drone::{ConnectionState, Drone, DroneState, FlightPhase},
+        faults::{Fault, FaultRequested, Faults},
+        telemetry::Telemetry,
+    },
+    io::{
+        impairment::{Impairment, MAX_LATENCY_MS, MIN_BANDWIDTH},
+        protocol::{ProtocolConfig, ProtocolVersion, SigningConfig},
+        transport::TransportConfig,
     },
-    io::{IOMessage, IOResource},
-    mavlink::dialects::serpe_dialect::{self, messages::Unregister},
     misc::selected_drone::SelectedDrone,
 };
-use bevy::prelude::*;
+use bevy::{
+    ecs::{query::QueryData, system::SystemParam},
+    prelude::*,
+};
 use bevy_egui::{egui, EguiContexts};
 
+use super::world_position;
+
 const COORDINATES_DRAG_SPEED: f64 = 0.00001;
+const MAX_CRUISE_SPEED: f64 = 100.0;
+const MAX_CRUISE_ALTITUDE: f64 = 500.0;
+const MAX_CLIMB_RATE: f64 = 20.0;
+const MIN_TELEMETRY_INTERVAL: f64 = 0.05;
+const MAX_TELEMETRY_INTERVAL: f64 = 60.0;
+const DEFAULT_BANDWIDTH: f64 = 1000.0;
+const MAX_FAULT_DELAY: f64 = 3600.0;
 
-pub fn show_right_window(
-    commands: &mut Commands,
-    contexts: &mut EguiContexts,
-    selected_drone: &mut ResMut<SelectedDrone>,
-    drones_query: &mut Query<(Entity, &mut Drone, Option<&mut Connection>)>,
-    io_sender: &mut ResMut<IOResource>,
-    camera_query: &mut Query<&mut Transform, With<Camera2d>>,
-) {
-    if let Some(selected_entity) = selected_drone.entity {
-        if let Ok((entity, mut drone, connection)) = drones_query.get_mut(selected_entity) {
-            show_drone_details_window(
-                commands,
-                contexts,
-                entity,
-                &mut drone,
-                connection,
-                selected_drone,
-                io_sender,
-                camera_query,
-            );
+/// Everything the details window shows or edits about the selected drone.
+#[derive(QueryData)]
+#[query_data(mutable)]
+pub struct DroneDetailsQuery {
+    entity: Entity,
+    drone: &'static mut Drone,
+    connection: Option<&'static Connection>,
+    battery: Option<&'static mut Battery>,
+    telemetry: Option<&'static mut Telemetry>,
+    impairment: Option<&'static mut Impairment>,
+    faults: Option<&'static mut Faults>,
+    status: ConnectionStatus,
+}
+
+/// Settings of the fault scheduler, kept between frames.
+#[derive(Resource)]
+pub struct FaultTool {
+    fault: Fault,
+    /// In simulated seconds.
+    delay: f64,
+}
+
+impl Default for FaultTool {
+    fn default() -> Self {
+        Self {
+            fault: Fault::PowerLoss,
+            delay: 10.0,
         }
     }
 }
 
-fn show_drone_details_window(
-    commands: &mut Commands,
-    contexts: &mut EguiContexts,
-    entity: Entity,
-    drone: &mut Drone,
-    connection: Option<Mut<Connection>>,
-    selected_drone: &mut ResMut<SelectedDrone>,
-    io_sender: &mut ResMut<IOResource>,
-    camera_query: &mut Query<&mut Transform, With<Camera2d>>,
-) {
+/// Everything the details window reads or changes.
+#[derive(SystemParam)]
+pub struct RightPanelParams<'w, 's> {
+    selected_drone: ResMut<'w, SelectedDrone>,
+    drones_query: Query<'w, 's, DroneDetailsQuery>,
+    actions: EventWriter<'w, DroneActionRequested>,
+    fault_requests: EventWriter<'w, FaultRequested>,
+    fault_tool: ResMut<'w, FaultTool>,
+    ground_station: Res<'w, GroundStationConfig>,
+    camera_query: Query<'w, 's, &'static mut Transform, With<Camera2d>>,
+}
+
+pub fn show_right_window(contexts: &mut EguiContexts, params: &mut RightPanelParams) {
+    let Some(selected_entity) = params.selected_drone.entity else {
+        return;
+    };
+    let Ok(mut details) = params.drones_query.get_mut(selected_entity) else {
+        return;
+    };
+
     let screen_width = contexts.ctx_mut().screen_rect().max.x;
     let window_pos = egui::pos2(screen_width - 310.0, 100.0);
 
@@ -59,128 +94,271 @@ fn show_drone_details_window(
         .open(&mut is_open)
         .show(contexts.ctx_mut(), |ui| {
             render_drone_details(
-                commands,
                 ui,
-                entity,
-                drone,
-                connection,
-                io_sender,
-                camera_query,
+                &mut details,
+                &mut params.actions,
+                &mut params.fault_requests,
+                &mut params.fault_tool,
+                &params.ground_station,
+                &mut params.camera_query,
             );
         });
 
     if !is_open {
-        selected_drone.entity = None;
+        params.selected_drone.entity = None;
     }
 }
 
 fn render_drone_details(
-    commands: &mut Commands,
     ui: &mut egui::Ui,
-    entity: Entity,
-    drone: &mut Drone,
-    connection: Option<Mut<Connection>>,
-    io_sender: &mut ResMut<IOResource>,
+    details: &mut DroneDetailsQueryItem,
+    actions: &mut EventWriter<DroneActionRequested>,
+    fault_requests: &mut EventWriter<FaultRequested>,
+    fault_tool: &mut FaultTool,
+    ground_station: &GroundStationConfig,
     camera_query: &mut Query<&mut Transform, With<Camera2d>>,
 ) {
-    render_drone_header(ui, drone);
+    let connection_state = details.status.state(details.connection);
+
+    render_drone_header(ui, &details.drone);
+    ui.separator();
+    let editable = matches!(
+        connection_state,
+        ConnectionState::Disconnected
+            | ConnectionState::Failed { .. }
+            | ConnectionState::Reconnecting { .. }
+    );
+    render_ground_station(ui, &mut details.drone, ground_station, editable);
+    ui.separator();
+    render_drone_state(ui, details, connection_state, actions);
     ui.separator();
-    render_drone_state(commands, ui, entity, drone, connection, io_sender);
+    render_drone_coordinates(ui, &mut details.drone.coordinates, camera_query);
     ui.separator();
-    render_drone_coordinates(ui, &mut drone.coordinates, camera_query);
+    render_drone_flight(ui, &mut details.drone);
+    if let Some(battery) = details.battery.as_deref_mut() {
+        ui.separator();
+        render_drone_battery(ui, battery, details.drone.phase);
+    }
+    if let Some(telemetry) = details.telemetry.as_deref_mut() {
+        ui.separator();
+        render_drone_telemetry(ui, telemetry);
+    }
+    if let Some(impairment) = details.impairment.as_mut() {
+        ui.separator();
+        // Edit a copy so the IO tasks are only told about actual changes
+        let mut edited = impairment.clone();
+        render_drone_impairment(ui, &mut edited);
+        impairment.set_if_neq(edited);
+    }
+    // Reborrowed rather than dereferenced, so `Faults` only shows up as
+    // changed (and the tampering resent) on an actual edit
+    if let Some(faults) = details.faults.as_mut() {
+        ui.separator();
+        render_drone_faults(
+            ui,
+            details.entity,
+            &details.drone,
+            faults,
+            fault_requests,
+            fault_tool,
+        );
+    }
 }
 
 fn render_drone_header(ui: &mut egui::Ui, drone: &Drone) {
     ui.heading(format!("Agent ID: {}", drone.agent_id));
 }
 
-fn render_drone_state(
-    commands: &mut Commands,
+fn render_ground_station(
     ui: &mut egui::Ui,
-    entity: Entity,
     drone: &mut Drone,
-    connection: Option<Mut<Connection>>,
-    io_sender: &mut ResMut<IOResource>,
+    ground_station: &GroundStationConfig,
+    editable: bool,
 ) {
+    ui.add_enabled_ui(editable, |ui| {
+        let mut override_address = drone.ground_station.is_some();
+        if ui
+            .checkbox(&mut override_address, "Override Ground Station")
+            .changed()
+        {
+            drone.ground_station = override_address.then(|| ground_station.address.clone());
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Address:");
+            match drone.ground_station.as_mut() {
+                Some(address) => {
+                    ui.text_edit_singleline(address);
+                }
+                None => {
+                    ui.label(&ground_station.address);
+                }
+            }
+        });
+
+        render_transport(ui, &mut drone.transport);
+        render_protocol(ui, &mut drone.protocol);
+    });
+}
+
+fn render_transport(ui: &mut egui::Ui, transport: &mut TransportConfig) {
+    ui.horizontal(|ui| {
+        ui.label("Transport:");
+        egui::ComboBox::from_id_source("transport")
+            .selected_text(transport.name())
+            .show_ui(ui, |ui| {
+                for kind in TransportConfig::kinds() {
+                    let selected = transport.is_same_kind(&kind);
+                    if ui.selectable_label(selected, kind.name()).clicked() && !selected {
+                        *transport = kind;
+                    }
+                }
+            });
+    });
+
+    match transport {
+        TransportConfig::TcpServer { address, shared } => {
+            ui.horizontal(|ui| {
+                ui.label("Listen on:");
+                ui.text_edit_singleline(address);
+            });
+            ui.checkbox(shared, "Share the port with other drones");
+        }
+        TransportConfig::Udp { bind } => {
+            ui.horizontal(|ui| {
+                ui.label("Bind:");
+                ui.text_edit_singleline(bind);
+            });
+        }
+        TransportConfig::Pty { link } => {
+            ui.horizontal(|ui| {
+                ui.label("Link:");
+                let mut path = link
+                    .as_ref()
+                    .map(|link| link.display().to_string())
+                    .unwrap_or_default();
+                if ui.text_edit_singleline(&mut path).changed() {
+                    *link = (!path.is_empty()).then(|| path.into());
+                }
+            });
+        }
+        TransportConfig::TcpClient | TransportConfig::Memory => {}
+    }
+}
+
+fn render_protocol(ui: &mut egui::Ui, protocol: &mut ProtocolConfig) {
+    ui.horizontal(|ui| {
+        ui.label("Protocol:");
+        egui::ComboBox::from_id_source("protocol")
+            .selected_text(protocol.version.to_string())
+            .show_ui(ui, |ui| {
+                for version in ProtocolVersion::ALL {
+                    ui.selectable_value(&mut protocol.version, version, version.to_string());
+                }
+            });
+    });
+    // MAVLink 1 frames have nowhere to put a signature
+    if protocol.version == ProtocolVersion::V1 {
+        protocol.signing = None;
+        return;
+    }
+
+    let mut signed = protocol.signing.is_some();
+    if ui.checkbox(&mut signed, "Sign Frames").changed() {
+        protocol.signing = signed.then(SigningConfig::default);
+    }
+    let Some(signing) = protocol.signing.as_mut() else {
+        return;
+    };
+
+    egui::Grid::new("signing").show(ui, |ui| {
+        ui.label("Key:");
+        ui.text_edit_singleline(&mut signing.key);
+        ui.end_row();
+
+        ui.label("Link ID:");
+        ui.add(egui::DragValue::new(&mut signing.link_id));
+        ui.end_row();
+    });
+    ui.checkbox(&mut signing.accept_unsigned, "Accept Unsigned Frames");
+    if let Err(err) = signing.secret() {
+        ui.label(err);
+    }
+}
+
+fn render_drone_state(
+    ui: &mut egui::Ui,
+    details: &DroneDetailsQueryItem,
+    connection_state: ConnectionState,
+    actions: &mut EventWriter<DroneActionRequested>,
+) {
+    let drone = &details.drone;
+    let mut request = |action| {
+        actions.send(DroneActionRequested {
+            entity: details.entity,
+            action,
+        });
+    };
     ui.label(format!("State: {}", drone.state));
 
+    let can_connect = matches!(
+        connection_state,
+        ConnectionState::Disconnected
+            | ConnectionState::Failed { .. }
+            | ConnectionState::Reconnecting { .. }
+    );
+
     if drone.state == DroneState::Offline {
         if ui.button("Turn On").clicked() {
-            drone.state = DroneState::Online;
+            request(DroneAction::TurnOn);
         }
-    } else if drone.state == DroneState::Online && connection.is_none() {
+    } else if drone.state == DroneState::Online && can_connect {
         if ui.button("Turn Off").clicked() {
-            drone.state = DroneState::Offline;
+            request(DroneAction::TurnOff);
         }
 
         if ui.button("Connect").clicked() {
-            match create_connection(drone.agent_id, io_sender, drone.coordinates) {
-                Ok(connection) => {
-                    commands.entity(entity).insert(connection);
-                }
-                Err(_) => {
-                    println!("Unsuccessful Connection");
-                }
-            }
+            request(DroneAction::Connect);
         }
     }
 
     if drone.state == DroneState::Online {
-        if let Some(mut connection) = connection {
-            let connection_status = if is_connection_broken(&connection) {
-                ConnectionState::Broken
-            } else {
-                ConnectionState::Connected
-            };
-            ui.label(format!("Connection Status: {}", connection_status));
+        ui.label(format!("Connection Status: {}", connection_state));
 
+        if let Some(connection) = details.connection {
             ui.label(format!("System ID: {}", connection.system_id));
 
-            if ui.button("Disconnect").clicked() {
-                on_disconnect(commands, entity, &mut connection);
+            render_link_health(ui, &connection.health);
+
+            if let Some(error) = &connection.last_error {
+                ui.label(format!("Last Error: {}", error));
+            }
+
+            if connection_state != ConnectionState::Disconnecting
+                && ui.button("Disconnect").clicked()
+            {
+                request(DroneAction::Disconnect);
             }
-        } else {
-            ui.label("No connection established.");
         }
     }
 }
 
-fn on_disconnect(commands: &mut Commands, entity: Entity, connection: &mut Connection) {
-    let _ = connection
-        .sender
-        .try_send(serpe_dialect::SerpeDialect::Unregister(Unregister {}));
-
-    std::thread::sleep(Duration::from_millis(100));
-    // TODO: wait for unregister ack
-
-    commands.entity(entity).remove::<Connection>();
-    connection.receiver.close();
-}
-
-fn create_connection(
-    agent_id: u32,
-    io_sender: &mut ResMut<IOResource>,
-    coordinates: Coordinates,
-) -> Result<Connection, ()> {
-    let (tx, rx) = tokio::sync::oneshot::channel();
-    let message = IOMessage::CreateConnection {
-        agent_id,
-        tx,
-        coordinates,
+fn render_link_health(ui: &mut egui::Ui, health: &LinkHealth) {
+    let latency = match health.latency {
+        Some(latency) => format!("{} ms", latency.as_millis()),
+        None => "-".to_string(),
     };
-
-    if let Err(_) = io_sender.sender.try_send(message) {
-        return Err(());
-    }
-
-    return match rx.blocking_recv() {
-        Ok(connection) => Ok(connection),
-        Err(_) => Err(()),
+    let last_ack = match health.last_ack_received {
+        Some(instant) => format!("{:.1} s ago", instant.elapsed().as_secs_f32()),
+        None => "never".to_string(),
     };
-}
 
-fn is_connection_broken(connection: &Connection) -> bool {
-    connection.receiver.is_closed()
+    ui.label(format!("Latency: {}", latency));
+    ui.label(format!("Last Heartbeat Ack: {}", last_ack));
+    ui.label(format!(
+        "Missed Acks: {} ({} in a row)",
+        health.missed_acks, health.consecutive_missed_acks
+    ));
 }
 
 fn render_drone_coordinates(
@@ -204,7 +382,271 @@ fn render_drone_coordinates(
 
     if ui.button("Center").clicked() {
         let mut camera = camera_query.single_mut();
-        camera.translation.x = coordinates.longitude * COORDS_ZOOM;
-        camera.translation.y = coordinates.latitude * COORDS_ZOOM;
+        let position = world_position(coordinates);
+        camera.translation.x = position.x;
+        camera.translation.y = position.y;
+    }
+}
+
+fn render_drone_flight(ui: &mut egui::Ui, drone: &mut Drone) {
+    ui.label(format!("Flight Phase: {}", drone.phase));
+    ui.label(format!("Altitude: {:.1} m", drone.coordinates.altitude));
+
+    egui::Grid::new("flight_parameters").show(ui, |ui| {
+        ui.label("Cruise Speed:");
+        ui.add(
+            egui::DragValue::new(&mut drone.cruise_speed)
+                .speed(0.1)
+                .range(0.1..=MAX_CRUISE_SPEED)
+                .suffix(" m/s"),
+        );
+        ui.end_row();
+
+        ui.label("Cruise Altitude:");
+        ui.add(
+            egui::DragValue::new(&mut drone.cruise_altitude)
+                .speed(0.5)
+                .range(1.0..=MAX_CRUISE_ALTITUDE)
+                .suffix(" m"),
+        );
+        ui.end_row();
+
+        ui.label("Climb Rate:");
+        ui.add(
+            egui::DragValue::new(&mut drone.climb_rate)
+                .speed(0.1)
+                .range(0.1..=MAX_CLIMB_RATE)
+                .suffix(" m/s"),
+        );
+        ui.end_row();
+    });
+}
+
+fn render_drone_battery(ui: &mut egui::Ui, battery: &mut Battery, phase: FlightPhase) {
+    let fraction = battery.fraction();
+    ui.horizontal(|ui| {
+        ui.label("Battery:");
+        ui.add(egui::ProgressBar::new(fraction as f32).text(format!(
+            "{:.0}% ({:.1}/{:.0} Wh)",
+            fraction * 100.0,
+            battery.charge,
+            battery.capacity
+        )));
+    });
+
+    ui.horizontal(|ui| {
+        ui.label("Low Threshold:");
+        let mut threshold = battery.low_threshold * 100.0;
+        if ui
+            .add(
+                egui::DragValue::new(&mut threshold)
+                    .speed(0.5)
+                    .range(0.0..=100.0)
+                    .suffix(" %"),
+            )
+            .changed()
+        {
+            battery.low_threshold = threshold / 100.0;
+        }
+    });
+
+    if ui
+        .add_enabled(phase == FlightPhase::Landed, egui::Button::new("Recharge"))
+        .clicked()
+    {
+        battery.recharge();
+    }
+}
+
+fn render_drone_telemetry(ui: &mut egui::Ui, telemetry: &mut Telemetry) {
+    ui.label("Telemetry");
+
+    let mut changed = false;
+    egui::Grid::new("telemetry").show(ui, |ui| {
+        for (label, value) in [
+            ("Heartbeat Every:", &mut telemetry.heartbeat_interval),
+            (
+                "Mission Update Every:",
+                &mut telemetry.mission_update_interval,
+            ),
+        ] {
+            ui.label(label);
+            changed |= ui
+                .add(
+                    egui::DragValue::new(value)
+                        .speed(0.05)
+                        .range(MIN_TELEMETRY_INTERVAL..=MAX_TELEMETRY_INTERVAL)
+                        .suffix(" s"),
+                )
+                .changed();
+            ui.end_row();
+        }
+
+        ui.label("Jitter:");
+        ui.add(
+            egui::DragValue::new(&mut telemetry.jitter)
+                .speed(0.01)
+                .range(0.0..=MAX_TELEMETRY_INTERVAL)
+                .prefix("± ")
+                .suffix(" s"),
+        );
+        ui.end_row();
+    });
+
+    if changed {
+        telemetry.apply_intervals();
+    }
+}
+
+fn render_drone_impairment(ui: &mut egui::Ui, impairment: &mut Impairment) {
+    ui.horizontal(|ui| {
+        ui.label("Network Impairment");
+        if ui
+            .add_enabled(!impairment.is_none(), egui::Button::new("Reset"))
+            .clicked()
+        {
+            *impairment = Impairment::default();
+        }
+    });
+
+    egui::Grid::new("impairment").show(ui, |ui| {
+        ui.label("Latency:");
+        ui.add(
+            egui::DragValue::new(&mut impairment.latency)
+                .speed(1.0)
+                .range(0.0..=MAX_LATENCY_MS)
+                .suffix(" ms"),
+        );
+        ui.end_row();
+
+        ui.label("Jitter:");
+        ui.add(
+            egui::DragValue::new(&mut impairment.jitter)
+                .speed(1.0)
+                .range(0.0..=MAX_LATENCY_MS)
+                .prefix("+ ")
+                .suffix(" ms"),
+        );
+        ui.end_row();
+
+        for (label, rate) in [
+            ("Drop:", &mut impairment.drop_rate),
+            ("Duplicate:", &mut impairment.duplicate_rate),
+        ] {
+            ui.label(label);
+            let mut percent = *rate * 100.0;
+            if ui
+                .add(
+                    egui::DragValue::new(&mut percent)
+                        .speed(0.5)
+                        .range(0.0..=100.0)
+                        .suffix(" %"),
+                )
+                .changed()
+            {
+                *rate = percent / 100.0;
+            }
+            ui.end_row();
+        }
+
+        let mut limited = impairment.bandwidth.is_some();
+        if ui.checkbox(&mut limited, "Bandwidth:").changed() {
+            impairment.bandwidth = limited.then_some(DEFAULT_BANDWIDTH);
+        }
+        match impairment.bandwidth.as_mut() {
+            Some(bandwidth) => {
+                ui.add(
+                    egui::DragValue::new(bandwidth)
+                        .speed(10.0)
+                        .range(MIN_BANDWIDTH..=1_000_000.0)
+                        .suffix(" B/s"),
+                );
+            }
+            None => {
+                ui.label("Unlimited");
+            }
+        }
+        ui.end_row();
+    });
+}
+
+fn render_drone_faults(
+    ui: &mut egui::Ui,
+    entity: Entity,
+    drone: &Drone,
+    faults: &mut Mut<Faults>,
+    fault_requests: &mut EventWriter<FaultRequested>,
+    fault_tool: &mut FaultTool,
+) {
+    let mut request = |fault, active| {
+        fault_requests.send(FaultRequested {
+            entity,
+            fault,
+            active,
+        });
+    };
+
+    ui.label("Faults");
+
+    ui.horizontal(|ui| {
+        for fault in Fault::ALL.into_iter().filter(|fault| fault.is_one_shot()) {
+            if ui.button(fault.to_string()).clicked() {
+                request(fault, true);
+            }
+        }
+    });
+    for fault in Fault::ALL.into_iter().filter(|fault| !fault.is_one_shot()) {
+        let mut active = faults.is_active(fault);
+        if ui.checkbox(&mut active, fault.to_string()).changed() {
+            request(fault, active);
+        }
+    }
+
+    let gps_error = drone
+        .coordinates
+        .distance_to(&faults.gps_position(&drone.coordinates));
+    if gps_error > 0.0 {
+        ui.label(format!("GPS Error: {:.1} m", gps_error));
+    }
+
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source("fault_tool")
+            .selected_text(fault_tool.fault.to_string())
+            .show_ui(ui, |ui| {
+                for fault in Fault::ALL {
+                    ui.selectable_value(&mut fault_tool.fault, fault, fault.to_string());
+                }
+            });
+        ui.label("in");
+        ui.add(
+            egui::DragValue::new(&mut fault_tool.delay)
+                .speed(0.5)
+                .range(0.0..=MAX_FAULT_DELAY)
+                .suffix(" s"),
+        );
+        if ui.button("Schedule").clicked() {
+            faults.schedule(fault_tool.fault, true, fault_tool.delay);
+        }
+    });
+
+    let mut cancelled = None;
+    for (index, scheduled) in faults.scheduled().iter().enumerate() {
+        ui.horizontal(|ui| {
+            let verb = if scheduled.fault.is_one_shot() || scheduled.active {
+                ""
+            } else {
+                "clear "
+            };
+            ui.label(format!(
+                "{}{} in {:.1} s",
+                verb, scheduled.fault, scheduled.remaining
+            ));
+            if ui.small_button("Cancel").clicked() {
+                cancelled = Some(index);
+            }
+        });
+    }
+    if let Some(index) = cancelled {
+        faults.cancel_scheduled(index);
     }
 }
@@ -0,0 +1,519 @@
+//! Shared harness: a scripted ground station on a local socket, and the
+//! simulator's ECS run headlessly one frame at a time.
+
+#![allow(dead_code)]
+
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use mavio::{prelude::Versionless, AsyncReceiver, AsyncSender, Frame, Message};
+use simulator::{
+    config::GroundStationConfig,
+    domain::{
+        connection::{Connection, LinkHealth},
+        coordinates::{Coordinates, DEFAULT_COORDINATES},
+        drone::Drone,
+    },
+    io::{
+        error::IoError,
+        impairment::Impairment,
+        protocol::{FrameVerifier, Framer, ProtocolConfig, Rejection, Tampering},
+        run_io,
+        transport::{
+            memory::MemoryListener, udp, TransportConfig, TransportReader, TransportWriter,
+        },
+        IOResource,
+    },
+    mavlink::dialects::{
+        serpe_dialect::messages::{HeartbeatAck, RegisterAck},
+        SerpeDialect,
+    },
+    misc::{
+        clock::{ClockControl, SimulationClock},
+        rng::SimulationRng,
+    },
+    scenario::{DroneSpec, Scenario},
+    simulation::SimulationPlugin,
+};
+use tokio::{
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::{mpsc, oneshot, watch},
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+/// Fast enough for a whole mission to fly in well under a second.
+pub const TIME_SCALE: f64 = 50.0;
+pub const AGENT_ID: u32 = 7;
+pub const SYSTEM_ID: u8 = 1;
+pub const GROUND_STATION_SYSTEM_ID: u8 = 255;
+
+pub const RECV_TIMEOUT: Duration = Duration::from_secs(10);
+const FRAME_INTERVAL: Duration = Duration::from_millis(2);
+
+/// Which way a message went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    ToGroundStation,
+    ToDrone,
+}
+
+pub type Transcript = Vec<(Direction, SerpeDialect)>;
+
+/// Stands in for the ground station, on whichever transport the test's
+/// drone uses. Tests script its side of the exchange with the `TestLink` it
+/// accepts.
+pub struct TestGroundStation {
+    listener: Listener,
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    Udp(UdpSocket),
+    Memory(MemoryListener),
+    /// The drone listens, the ground station dials this address.
+    Dial(String),
+    /// The drone's pseudo-terminal, once it is linked here.
+    Pty(PathBuf),
+}
+
+impl TestGroundStation {
+    pub async fn bind() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        Self {
+            listener: Listener::Tcp(listener),
+        }
+    }
+
+    /// Listens on `address` again, once the last ground station there is
+    /// gone, for a drone to reconnect to.
+    pub async fn rebind(address: &str) -> Self {
+        let listener = TcpListener::bind(address).await.unwrap();
+        Self {
+            listener: Listener::Tcp(listener),
+        }
+    }
+
+    pub async fn bind_udp() -> Self {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        Self {
+            listener: Listener::Udp(socket),
+        }
+    }
+
+    pub fn bind_memory(name: &str) -> Self {
+        Self {
+            listener: Listener::Memory(MemoryListener::bind(name).unwrap()),
+        }
+    }
+
+    pub fn dial(address: String) -> Self {
+        Self {
+            listener: Listener::Dial(address),
+        }
+    }
+
+    pub fn open_pty(link: PathBuf) -> Self {
+        Self {
+            listener: Listener::Pty(link),
+        }
+    }
+
+    /// What the drone's ground-station address has to be.
+    pub fn address(&self) -> String {
+        match &self.listener {
+            Listener::Tcp(listener) => listener.local_addr().unwrap().to_string(),
+            Listener::Udp(socket) => socket.local_addr().unwrap().to_string(),
+            Listener::Memory(listener) => listener.name().to_string(),
+            Listener::Dial(_) | Listener::Pty(_) => "unused".to_string(),
+        }
+    }
+
+    pub async fn accept(self) -> TestLink {
+        self.accept_with(ProtocolConfig::default()).await
+    }
+
+    /// Accepts a drone that speaks `protocol`. Its frames have to pass the
+    /// same checks the drone applies, see `TestLink::recv_from`.
+    pub async fn accept_with(self, protocol: ProtocolConfig) -> TestLink {
+        let (reader, writer) = tokio::time::timeout(RECV_TIMEOUT, self.listener.accept())
+            .await
+            .expect("no drone connected");
+
+        TestLink {
+            receiver: AsyncReceiver::versionless(reader),
+            sender: AsyncSender::versionless(writer),
+            framer: Framer::new(GROUND_STATION_SYSTEM_ID, &protocol).unwrap(),
+            verifier: FrameVerifier::new(&protocol).unwrap(),
+            protocol,
+            transcript: Transcript::new(),
+        }
+    }
+}
+
+impl Listener {
+    async fn accept(self) -> (TransportReader, TransportWriter) {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (reader, writer) = stream.into_split();
+                (Box::new(reader), Box::new(writer))
+            }
+            Listener::Udp(socket) => {
+                // Talk back to wherever the `Register` came from
+                let mut first = [0; 1];
+                let (_, peer) = socket.peek_from(&mut first).await.unwrap();
+                socket.connect(peer).await.unwrap();
+                let (reader, writer) = udp::split(socket);
+                (Box::new(reader), Box::new(writer))
+            }
+            Listener::Memory(mut listener) => {
+                let stream = listener.accept().await.unwrap();
+                let (reader, writer) = tokio::io::split(stream);
+                (Box::new(reader), Box::new(writer))
+            }
+            Listener::Dial(address) => loop {
+                // The drone only starts listening once it is spawned
+                if let Ok(stream) = TcpStream::connect(&address).await {
+                    let (reader, writer) = stream.into_split();
+                    break (Box::new(reader), Box::new(writer));
+                }
+                tokio::time::sleep(FRAME_INTERVAL).await;
+            },
+            Listener::Pty(link) => loop {
+                if let Ok(port) = std::fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(&link)
+                {
+                    let (reader, writer) = tokio::io::split(tokio::fs::File::from_std(port));
+                    break (Box::new(reader), Box::new(writer));
+                }
+                tokio::time::sleep(FRAME_INTERVAL).await;
+            },
+        }
+    }
+}
+
+/// One drone's connection, recording everything sent either way.
+pub struct TestLink {
+    receiver: AsyncReceiver<TransportReader, Versionless>,
+    sender: AsyncSender<TransportWriter, Versionless>,
+    protocol: ProtocolConfig,
+    framer: Framer,
+    verifier: FrameVerifier,
+    pub transcript: Transcript,
+}
+
+impl TestLink {
+    pub async fn recv(&mut self) -> SerpeDialect {
+        self.recv_from().await.1
+    }
+
+    /// Also returns the system id the frame came from.
+    pub async fn recv_from(&mut self) -> (u8, SerpeDialect) {
+        let frame = self.recv_frame().await;
+        if let Err(rejection) = self.verify(&frame) {
+            panic!("the drone sent a {} frame", rejection);
+        }
+        let message = frame.decode::<SerpeDialect>().unwrap();
+
+        self.transcript
+            .push((Direction::ToGroundStation, message.clone()));
+        (frame.system_id(), message)
+    }
+
+    /// Receives until `predicate` matches, acking heartbeats on the way like
+    /// a real ground station would.
+    pub async fn recv_until(&mut self, predicate: impl Fn(&SerpeDialect) -> bool) -> SerpeDialect {
+        loop {
+            let message = self.recv().await;
+            if predicate(&message) {
+                return message;
+            }
+            if matches!(message, SerpeDialect::Heartbeat(_)) {
+                self.send(SerpeDialect::HeartbeatAck(HeartbeatAck {})).await;
+            }
+        }
+    }
+
+    /// The next frame as it is, without checking or recording it.
+    pub async fn recv_frame(&mut self) -> Frame<Versionless> {
+        tokio::time::timeout(RECV_TIMEOUT, self.receiver.recv())
+            .await
+            .expect("the drone went quiet")
+            .unwrap()
+    }
+
+    /// Checks a frame from the drone against the link's protocol.
+    pub fn verify(&mut self, frame: &Frame<Versionless>) -> Result<(), Rejection> {
+        self.verifier.check(frame)
+    }
+
+    pub async fn send(&mut self, message: SerpeDialect) {
+        let frame = self.frame(&message);
+        self.send_frame(frame, message).await;
+    }
+
+    /// Sends with `system_id` in the header, which is how a shared port
+    /// tells the drones apart.
+    pub async fn send_to(&mut self, system_id: u8, message: SerpeDialect) {
+        let mut framer = Framer::new(system_id, &self.protocol).unwrap();
+        let frame = framer.next_frame(as_message(&message)).unwrap();
+        self.send_frame(frame, message).await;
+    }
+
+    /// Frames `message` the way `send` would, to send it later or more
+    /// than once.
+    pub fn frame(&mut self, message: &SerpeDialect) -> Frame<Versionless> {
+        self.framer.next_frame(as_message(message)).unwrap()
+    }
+
+    /// Sends `frame` as it is, recorded as `message`.
+    pub async fn send_frame(&mut self, frame: Frame<Versionless>, message: SerpeDialect) {
+        self.sender.send(&frame).await.unwrap();
+        self.sender.flush().await.unwrap();
+
+        self.transcript.push((Direction::ToDrone, message));
+    }
+
+    /// Expects the drone's `Register` and acks it with `SYSTEM_ID`.
+    pub async fn register(&mut self) {
+        let message = self.recv().await;
+        let SerpeDialect::Register(register) = message else {
+            panic!("expected Register, got {:?}", message);
+        };
+        assert_eq!(register.agent_id, AGENT_ID);
+
+        self.send(SerpeDialect::RegisterAck(RegisterAck {
+            system_id: SYSTEM_ID,
+        }))
+        .await;
+    }
+}
+
+pub fn as_message(message: &SerpeDialect) -> &dyn Message {
+    match message {
+        SerpeDialect::Register(msg) => msg,
+        SerpeDialect::RegisterAck(msg) => msg,
+        SerpeDialect::Unregister(msg) => msg,
+        SerpeDialect::UnregisterAck(msg) => msg,
+        SerpeDialect::Heartbeat(msg) => msg,
+        SerpeDialect::HeartbeatAck(msg) => msg,
+        SerpeDialect::MissionRequest(msg) => msg,
+        SerpeDialect::MissionAccept(msg) => msg,
+        SerpeDialect::MissionAcceptAck(msg) => msg,
+        SerpeDialect::MissionUpdate(msg) => msg,
+        SerpeDialect::MissionFinished(msg) => msg,
+        SerpeDialect::MissionFinishedAck(msg) => msg,
+        SerpeDialect::MissionCount(msg) => msg,
+        SerpeDialect::MissionItemRequest(msg) => msg,
+        SerpeDialect::MissionItem(msg) => msg,
+        SerpeDialect::MissionAck(msg) => msg,
+        SerpeDialect::MissionAbort(msg) => msg,
+    }
+}
+
+/// The variant name of `message`, e.g. `"MissionAccept"`.
+pub fn name(message: &SerpeDialect) -> String {
+    let debug = format!("{:?}", message);
+    debug.split('(').next().unwrap().to_string()
+}
+
+/// The message names in `transcript`, leaving out those in `ignored` and
+/// collapsing repeats (`MissionUpdate…`) into one.
+pub fn sequence(transcript: &Transcript, ignored: &[&str]) -> Vec<String> {
+    let mut names: Vec<String> = transcript
+        .iter()
+        .map(|(_, message)| name(message))
+        .filter(|name| !ignored.contains(&name.as_str()))
+        .collect();
+    names.dedup();
+    names
+}
+
+/// The simulator with drones that connect to `address` at startup, a
+/// single one unless the test brings its own scenario.
+pub struct Simulator {
+    pub app: App,
+    token: CancellationToken,
+}
+
+impl Simulator {
+    pub fn new(address: String) -> Self {
+        Self::with_transport(address, TransportConfig::default())
+    }
+
+    pub fn with_transport(address: String, transport: TransportConfig) -> Self {
+        Self::with_drone(
+            address,
+            DroneSpec {
+                transport: Some(transport),
+                ..Default::default()
+            },
+        )
+    }
+
+    pub fn with_protocol(address: String, protocol: ProtocolConfig) -> Self {
+        Self::with_drone(
+            address,
+            DroneSpec {
+                protocol,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// A single drone set up by `spec`, with the test's agent id and
+    /// connecting at startup.
+    pub fn with_drone(address: String, spec: DroneSpec) -> Self {
+        let scenario = Scenario {
+            drones: vec![DroneSpec {
+                agent_id: Some(AGENT_ID),
+                connect: true,
+                ..spec
+            }],
+            ..Default::default()
+        };
+        Self::with_scenario(address, scenario)
+    }
+
+    pub fn with_scenario(address: String, scenario: Scenario) -> Self {
+        let seed = scenario.seed.unwrap_or(0);
+        Self::build(
+            address,
+            scenario,
+            SimulationClock::new(false, TIME_SCALE),
+            seed,
+        )
+    }
+
+    /// The simulator with its clock stopped, seeded as with `--seed`. Only
+    /// `step` moves it on, so runs can be compared tick for tick.
+    pub fn paused(scenario: Scenario, seed: u64) -> Self {
+        let address = GroundStationConfig::default().address;
+        Self::build(
+            address,
+            scenario,
+            SimulationClock::new(true, TIME_SCALE),
+            seed,
+        )
+    }
+
+    fn build(address: String, scenario: Scenario, clock: SimulationClock, seed: u64) -> Self {
+        let token = CancellationToken::new();
+        let (sender, receiver) = tokio::sync::mpsc::channel(1000);
+        tokio::spawn(run_io(receiver, token.clone(), TaskTracker::new()));
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(IOResource { sender })
+            .insert_resource(GroundStationConfig { address })
+            .insert_resource(clock)
+            .insert_resource(Time::<Fixed>::from_hz(60.0))
+            .insert_resource(SimulationRng::seeded(seed))
+            .insert_resource(scenario)
+            .add_plugins(SimulationPlugin);
+        app.finish();
+        app.cleanup();
+        // Spawns the drones, which start connecting right away
+        app.update();
+
+        Self { app, token }
+    }
+
+    /// Runs frames until `done` holds, giving the IO tasks time in between.
+    pub async fn run_until(&mut self, mut done: impl FnMut(&mut World) -> bool) {
+        let started = Instant::now();
+        loop {
+            self.app.update();
+            if done(self.app.world_mut()) {
+                return;
+            }
+            assert!(started.elapsed() < RECV_TIMEOUT, "simulation timed out");
+            tokio::time::sleep(FRAME_INTERVAL).await;
+        }
+    }
+
+    /// Runs exactly `ticks` `FixedUpdate` ticks of a paused simulator.
+    pub fn step(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.app.world_mut().send_event(ClockControl::Step);
+            self.app.update();
+        }
+        // A step is taken on the frame after the one that reads it
+        self.app.update();
+    }
+
+    /// Gives the drone at `entity` a link with nothing on the far end, to
+    /// read what it sends without a ground station in the way.
+    pub fn loopback(&mut self, entity: Entity) -> Loopback {
+        let (incoming, receiver) = mpsc::channel(256);
+        let (sender, sent) = mpsc::channel(256);
+        let (error, error_receiver) = oneshot::channel();
+        let (impairment, _) = watch::channel(Impairment::default());
+        let (tampering, _) = watch::channel(Tampering::default());
+
+        self.app.world_mut().entity_mut(entity).insert(Connection {
+            system_id: SYSTEM_ID,
+            receiver,
+            sender,
+            error_receiver,
+            last_error: None,
+            health: LinkHealth::default(),
+            impairment,
+            tampering,
+        });
+        Loopback {
+            sent,
+            _incoming: incoming,
+            _error: error,
+        }
+    }
+
+    pub fn drone(&mut self) -> Entity {
+        self.agent(AGENT_ID)
+    }
+
+    pub fn agent(&mut self, agent_id: u32) -> Entity {
+        let world = self.app.world_mut();
+        let mut query = world.query::<(Entity, &Drone)>();
+        query
+            .iter(world)
+            .find(|(_, drone)| drone.agent_id == agent_id)
+            .map(|(entity, _)| entity)
+            .expect("the scenario drone was not spawned")
+    }
+}
+
+impl Drop for Simulator {
+    fn drop(&mut self) {
+        self.token.cancel();
+    }
+}
+
+/// The far end of a `Simulator::loopback` link. Keeps the link up for as
+/// long as it lives.
+pub struct Loopback {
+    sent: mpsc::Receiver<SerpeDialect>,
+    _incoming: mpsc::Sender<SerpeDialect>,
+    _error: oneshot::Sender<IoError>,
+}
+
+impl Loopback {
+    /// Everything the drone sent since the last call.
+    pub fn drain(&mut self) -> Vec<SerpeDialect> {
+        let mut messages = Vec::new();
+        while let Ok(message) = self.sent.try_recv() {
+            messages.push(message);
+        }
+        messages
+    }
+}
+
+/// A point `distance` metres north of where drones spawn.
+pub fn north_of_spawn(distance: f64) -> Coordinates {
+    DEFAULT_COORDINATES.destination(0.0, distance)
+}
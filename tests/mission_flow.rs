@@ -0,0 +1,512 @@
+//! Drives whole missions between the simulator and a scripted ground station,
+//! checking both the messages on the wire and the `MissionState` each step
+//! leaves the drone in.
+
+mod common;
+
+use bevy::prelude::*;
+use common::{north_of_spawn, sequence, Simulator, TestGroundStation, AGENT_ID};
+use simulator::{
+    domain::{
+        actions::{DroneAction, DroneActionRequested},
+        battery::Battery,
+        connection::Connection,
+        coordinates::Coordinates,
+        drone::{Drone, DroneState, FlightPhase},
+        faults::{gps_position, Fault, Faults},
+        mission::{Mission, MissionResult, MissionState},
+    },
+    io::{impairment::Impairment, wire::encode_degrees},
+    mavlink::dialects::{
+        serpe_dialect::messages::{
+            HeartbeatAck, MissionAcceptAck, MissionCount, MissionFinishedAck, MissionItem,
+            MissionRequest, UnregisterAck,
+        },
+        SerpeDialect,
+    },
+    scenario::{DroneSpec, FaultSpec, Scenario, TelemetrySpec},
+};
+use tokio::sync::oneshot;
+
+/// Far enough for a few mission updates, short enough to fly fast.
+const MISSION_DISTANCE: f64 = 30.0;
+
+fn mission_request(distance: f64) -> SerpeDialect {
+    let target = north_of_spawn(distance);
+    SerpeDialect::MissionRequest(MissionRequest {
+        target_latitude: encode_degrees(target.latitude),
+        target_longitude: encode_degrees(target.longitude),
+    })
+}
+
+fn mission_item(seq: u16, distance: f64) -> SerpeDialect {
+    let waypoint = north_of_spawn(distance);
+    SerpeDialect::MissionItem(MissionItem {
+        seq,
+        latitude: encode_degrees(waypoint.latitude),
+        longitude: encode_degrees(waypoint.longitude),
+    })
+}
+
+/// Appends the drone's mission state to `states` whenever it changes.
+fn record_state(world: &mut World, entity: Entity, states: &mut Vec<Option<MissionState>>) {
+    let state = world
+        .get::<Mission>(entity)
+        .map(|mission| mission.state.clone());
+    if states.last() != Some(&state) {
+        states.push(state);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mission_request_runs_the_full_exchange() {
+    let ground_station = TestGroundStation::bind().await;
+    let mut simulator = Simulator::new(ground_station.address());
+
+    let script = tokio::spawn(async move {
+        let mut link = ground_station.accept().await;
+        link.register().await;
+        link.recv_until(|message| matches!(message, SerpeDialect::Heartbeat(_)))
+            .await;
+
+        link.send(mission_request(MISSION_DISTANCE)).await;
+        link.recv_until(|message| matches!(message, SerpeDialect::MissionAccept(_)))
+            .await;
+        link.send(SerpeDialect::MissionAcceptAck(MissionAcceptAck {}))
+            .await;
+        link.recv_until(|message| matches!(message, SerpeDialect::MissionFinished(_)))
+            .await;
+        link.send(SerpeDialect::MissionFinishedAck(MissionFinishedAck {}))
+            .await;
+        // Hand the link back so it stays open until the test ends
+        link
+    });
+
+    let entity = simulator.drone();
+    let mut states = Vec::new();
+    simulator
+        .run_until(|world| {
+            record_state(world, entity, &mut states);
+            script.is_finished() && !world.entity(entity).contains::<Mission>()
+        })
+        .await;
+    let link = script.await.unwrap();
+    let transcript = &link.transcript;
+
+    assert_eq!(
+        sequence(transcript, &["HeartbeatAck"])[..3],
+        ["Register", "RegisterAck", "Heartbeat"]
+    );
+    assert_eq!(
+        sequence(transcript, &["Heartbeat", "HeartbeatAck"]),
+        [
+            "Register",
+            "RegisterAck",
+            "MissionRequest",
+            "MissionAccept",
+            "MissionAcceptAck",
+            "MissionUpdate",
+            "MissionFinished",
+            "MissionFinishedAck",
+        ]
+    );
+    assert_eq!(
+        states,
+        [
+            None,
+            Some(MissionState::AwaitingAcceptAck),
+            Some(MissionState::Ongoing),
+            Some(MissionState::AwaitingFinishedAck),
+            None,
+        ]
+    );
+
+    let drone = simulator.app.world().get::<Drone>(entity).unwrap();
+    assert_eq!(drone.phase, FlightPhase::Landed);
+    assert!(
+        drone
+            .coordinates
+            .distance_to(&north_of_spawn(MISSION_DISTANCE))
+            < 0.01
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mission_upload_pulls_every_item() {
+    let ground_station = TestGroundStation::bind().await;
+    let mut simulator = Simulator::new(ground_station.address());
+
+    let script = tokio::spawn(async move {
+        let mut link = ground_station.accept().await;
+        link.register().await;
+
+        link.send(SerpeDialect::MissionCount(MissionCount { count: 2 }))
+            .await;
+        for seq in 0..2 {
+            let request = link
+                .recv_until(|message| matches!(message, SerpeDialect::MissionItemRequest(_)))
+                .await;
+            assert!(matches!(request, SerpeDialect::MissionItemRequest(msg) if msg.seq == seq));
+            link.send(mission_item(seq, MISSION_DISTANCE * (seq + 1) as f64))
+                .await;
+        }
+        let ack = link
+            .recv_until(|message| matches!(message, SerpeDialect::MissionAck(_)))
+            .await;
+        assert!(
+            matches!(ack, SerpeDialect::MissionAck(msg) if msg.result == MissionResult::Accepted as u8)
+        );
+
+        link.recv_until(|message| matches!(message, SerpeDialect::MissionFinished(_)))
+            .await;
+        link.send(SerpeDialect::MissionFinishedAck(MissionFinishedAck {}))
+            .await;
+        // Hand the link back so it stays open until the test ends
+        link
+    });
+
+    let entity = simulator.drone();
+    let mut states = Vec::new();
+    simulator
+        .run_until(|world| {
+            record_state(world, entity, &mut states);
+            script.is_finished() && !world.entity(entity).contains::<Mission>()
+        })
+        .await;
+    let link = script.await.unwrap();
+    let transcript = &link.transcript;
+
+    assert_eq!(
+        sequence(transcript, &["Heartbeat", "HeartbeatAck"]),
+        [
+            "Register",
+            "RegisterAck",
+            "MissionCount",
+            "MissionItemRequest",
+            "MissionItem",
+            "MissionItemRequest",
+            "MissionItem",
+            "MissionAck",
+            "MissionUpdate",
+            "MissionFinished",
+            "MissionFinishedAck",
+        ]
+    );
+    assert_eq!(
+        states,
+        [
+            None,
+            Some(MissionState::Ongoing),
+            Some(MissionState::AwaitingFinishedAck),
+            None,
+        ]
+    );
+
+    let drone = simulator.app.world().get::<Drone>(entity).unwrap();
+    assert!(
+        drone
+            .coordinates
+            .distance_to(&north_of_spawn(MISSION_DISTANCE * 2.0))
+            < 0.01
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn busy_drone_turns_down_new_missions() {
+    let ground_station = TestGroundStation::bind().await;
+    let mut simulator = Simulator::new(ground_station.address());
+
+    let script = tokio::spawn(async move {
+        let mut link = ground_station.accept().await;
+        link.register().await;
+
+        link.send(mission_request(MISSION_DISTANCE)).await;
+        link.recv_until(|message| matches!(message, SerpeDialect::MissionAccept(_)))
+            .await;
+        link.send(SerpeDialect::MissionAcceptAck(MissionAcceptAck {}))
+            .await;
+
+        // Neither kind of mission gets in while one is being flown
+        link.send(mission_request(MISSION_DISTANCE * 2.0)).await;
+        link.send(SerpeDialect::MissionCount(MissionCount { count: 1 }))
+            .await;
+        let ack = link
+            .recv_until(|message| matches!(message, SerpeDialect::MissionAck(_)))
+            .await;
+        assert!(
+            matches!(ack, SerpeDialect::MissionAck(msg) if msg.result == MissionResult::Busy as u8)
+        );
+
+        link.recv_until(|message| matches!(message, SerpeDialect::MissionFinished(_)))
+            .await;
+        link.send(SerpeDialect::MissionFinishedAck(MissionFinishedAck {}))
+            .await;
+        // Hand the link back so it stays open until the test ends
+        link
+    });
+
+    let entity = simulator.drone();
+    simulator
+        .run_until(|world| script.is_finished() && !world.entity(entity).contains::<Mission>())
+        .await;
+    let link = script.await.unwrap();
+    let transcript = &link.transcript;
+
+    let accepts = transcript
+        .iter()
+        .filter(|(_, message)| matches!(message, SerpeDialect::MissionAccept(_)))
+        .count();
+    assert_eq!(accepts, 1);
+
+    // The second request was ignored, the drone landed on the first target
+    let drone = simulator.app.world().get::<Drone>(entity).unwrap();
+    assert!(
+        drone
+            .coordinates
+            .distance_to(&north_of_spawn(MISSION_DISTANCE))
+            < 0.01
+    );
+}
+
+/// The link drops mid-mission and the drone lands before it is back. The
+/// `MissionFinished` goes out on the new link, which frees the drone for the
+/// next mission.
+#[tokio::test(flavor = "multi_thread")]
+async fn mission_finished_after_reconnecting() {
+    let ground_station = TestGroundStation::bind().await;
+    let address = ground_station.address();
+    let mut simulator = Simulator::new(address.clone());
+    let (landed_sender, landed) = oneshot::channel();
+    let (freed_sender, freed) = oneshot::channel();
+
+    let script = tokio::spawn(async move {
+        let mut link = ground_station.accept().await;
+        link.register().await;
+        link.send(mission_request(MISSION_DISTANCE)).await;
+        link.recv_until(|message| matches!(message, SerpeDialect::MissionAccept(_)))
+            .await;
+        link.send(SerpeDialect::MissionAcceptAck(MissionAcceptAck {}))
+            .await;
+        // Drop the link once the drone is on its way
+        link.recv_until(|message| matches!(message, SerpeDialect::MissionUpdate(_)))
+            .await;
+        drop(link);
+
+        // Nothing listens until the drone is down
+        landed.await.unwrap();
+        let mut link = TestGroundStation::rebind(&address).await.accept().await;
+        link.register().await;
+        link.recv_until(|message| matches!(message, SerpeDialect::MissionFinished(_)))
+            .await;
+        link.send(SerpeDialect::MissionFinishedAck(MissionFinishedAck {}))
+            .await;
+
+        freed.await.unwrap();
+        link.send(mission_request(MISSION_DISTANCE * 2.0)).await;
+        link.recv_until(|message| matches!(message, SerpeDialect::MissionAccept(_)))
+            .await;
+        link
+    });
+
+    let entity = simulator.drone();
+    simulator
+        .run_until(|world| {
+            !world.entity(entity).contains::<Connection>()
+                && world
+                    .get::<Mission>(entity)
+                    .is_some_and(|mission| mission.state == MissionState::AwaitingFinishedAck)
+        })
+        .await;
+    landed_sender.send(()).unwrap();
+    simulator
+        .run_until(|world| !world.entity(entity).contains::<Mission>())
+        .await;
+    freed_sender.send(()).unwrap();
+    simulator.run_until(|_| script.is_finished()).await;
+    let link = script.await.unwrap();
+
+    assert_eq!(
+        sequence(&link.transcript, &["Heartbeat", "HeartbeatAck"]),
+        [
+            "Register",
+            "RegisterAck",
+            "MissionFinished",
+            "MissionFinishedAck",
+            "MissionRequest",
+            "MissionAccept"
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn turning_off_unregisters() {
+    let ground_station = TestGroundStation::bind().await;
+    let mut simulator = Simulator::new(ground_station.address());
+
+    let entity = simulator.drone();
+    let script = tokio::spawn(async move {
+        let mut link = ground_station.accept().await;
+        link.register().await;
+        link.recv_until(|message| matches!(message, SerpeDialect::Unregister(_)))
+            .await;
+        link.send(SerpeDialect::UnregisterAck(UnregisterAck {}))
+            .await;
+        // Hand the link back so it stays open until the test ends
+        link
+    });
+
+    // Wait for the link before pulling the plug
+    simulator
+        .run_until(|world| {
+            let mut query = world.query_filtered::<(), With<Connection>>();
+            query.iter(world).next().is_some()
+        })
+        .await;
+    simulator.app.world_mut().send_event(DroneActionRequested {
+        entity,
+        action: DroneAction::TurnOff,
+    });
+
+    simulator
+        .run_until(|world| {
+            script.is_finished()
+                && world.get::<Drone>(entity).unwrap().state == DroneState::Offline
+                && !world.entity(entity).contains::<Connection>()
+        })
+        .await;
+    let link = script.await.unwrap();
+    let transcript = &link.transcript;
+
+    assert_eq!(
+        sequence(transcript, &["Heartbeat", "HeartbeatAck"]),
+        ["Register", "RegisterAck", "Unregister", "UnregisterAck"]
+    );
+}
+
+/// Flies three drones with drifting GPS and jittered telemetry for `TICKS`
+/// ticks and reads back, per drone, where it is, where its GPS says it is,
+/// its charge and what it sent.
+fn fly_fleet(seed: u64) -> Vec<(Coordinates, Coordinates, f64, Vec<String>)> {
+    const TICKS: u32 = 600;
+
+    let agent_ids = [AGENT_ID, AGENT_ID + 1, AGENT_ID + 2];
+    let scenario = Scenario {
+        drones: agent_ids
+            .iter()
+            .map(|agent_id| DroneSpec {
+                agent_id: Some(*agent_id),
+                online: true,
+                faults: vec![FaultSpec {
+                    kind: Fault::GpsDrift,
+                    at: 0.0,
+                    until: None,
+                }],
+                telemetry: Some(TelemetrySpec {
+                    jitter: Some(0.5),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let mut simulator = Simulator::paused(scenario, seed);
+
+    let mut links = Vec::new();
+    for (index, agent_id) in agent_ids.iter().enumerate() {
+        let entity = simulator.agent(*agent_id);
+        links.push(simulator.loopback(entity));
+        let target = north_of_spawn(MISSION_DISTANCE * (index + 1) as f64);
+        simulator
+            .app
+            .world_mut()
+            .entity_mut(entity)
+            .insert(Mission::new(MissionState::Ongoing, vec![target]));
+    }
+    simulator.step(TICKS);
+
+    agent_ids
+        .iter()
+        .zip(links.iter_mut())
+        .map(|(agent_id, link)| {
+            let entity = simulator.agent(*agent_id);
+            let world = simulator.app.world();
+            let drone = world.get::<Drone>(entity).unwrap();
+            let sent = link
+                .drain()
+                .iter()
+                .map(|message| format!("{:?}", message))
+                .collect();
+            (
+                drone.coordinates,
+                gps_position(drone, world.get::<Faults>(entity)),
+                world.get::<Battery>(entity).unwrap().charge,
+                sent,
+            )
+        })
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn same_seed_repeats_the_run() {
+    let run = fly_fleet(42);
+    // Jitter is only drawn for what actually goes out
+    for (.., sent) in &run {
+        assert!(sent.iter().any(|message| message.starts_with("Heartbeat(")));
+        assert!(sent
+            .iter()
+            .any(|message| message.starts_with("MissionUpdate(")));
+    }
+
+    assert_eq!(run, fly_fleet(42));
+    // The drift is random, so another seed has to show up somewhere
+    assert_ne!(run, fly_fleet(43));
+}
+
+/// Deleting a drone drops its `Connection` at once. A frame still on its way
+/// in must not take the link down before the `Unregister`, which the latency
+/// holds back too, went out and was acked.
+#[tokio::test(flavor = "multi_thread")]
+async fn deleting_unregisters_over_a_slow_link() {
+    let ground_station = TestGroundStation::bind().await;
+    let spec = DroneSpec {
+        impairment: Impairment {
+            latency: 200.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut simulator = Simulator::with_drone(ground_station.address(), spec);
+    let (acked_sender, mut acked) = oneshot::channel();
+
+    let script = tokio::spawn(async move {
+        let mut link = ground_station.accept().await;
+        link.register().await;
+        link.recv_until(|message| matches!(message, SerpeDialect::Heartbeat(_)))
+            .await;
+        link.send(SerpeDialect::HeartbeatAck(HeartbeatAck {})).await;
+        acked_sender.send(()).unwrap();
+
+        link.recv_until(|message| matches!(message, SerpeDialect::Unregister(_)))
+            .await;
+        link.send(SerpeDialect::UnregisterAck(UnregisterAck {}))
+            .await;
+        link
+    });
+
+    // Delete while the ack is still held back on the drone's side
+    let entity = simulator.drone();
+    simulator.run_until(|_| acked.try_recv().is_ok()).await;
+    simulator.app.world_mut().send_event(DroneActionRequested {
+        entity,
+        action: DroneAction::Delete,
+    });
+
+    simulator.run_until(|_| script.is_finished()).await;
+    let link = script.await.unwrap();
+
+    assert_eq!(
+        sequence(&link.transcript, &["Heartbeat", "HeartbeatAck"]),
+        ["Register", "RegisterAck", "Unregister", "UnregisterAck"]
+    );
+}
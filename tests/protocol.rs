@@ -0,0 +1,244 @@
+//! MAVLink 1 links and signed MAVLink 2 links, from the handshake on, and
+//! what each side does with frames that fail the signing checks.
+
+mod common;
+
+use common::{
+    as_message, north_of_spawn, Simulator, TestGroundStation, GROUND_STATION_SYSTEM_ID, SYSTEM_ID,
+};
+use simulator::{
+    domain::{
+        connection::{Connection, FailedConnection},
+        drone::Drone,
+        faults::Fault,
+        mission::Mission,
+    },
+    io::{
+        error::IoError,
+        protocol::{Framer, ProtocolConfig, ProtocolVersion, Rejection, SigningConfig},
+        wire::encode_degrees,
+    },
+    mavlink::dialects::{
+        serpe_dialect::messages::{
+            HeartbeatAck, MissionAcceptAck, MissionFinishedAck, MissionRequest,
+        },
+        SerpeDialect,
+    },
+    scenario::{DroneSpec, FaultSpec},
+};
+use tokio::sync::oneshot;
+
+const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+const OTHER_KEY: &str = "ff0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+/// Short enough to fly fast.
+const MISSION_DISTANCE: f64 = 30.0;
+
+fn signed(key: &str) -> ProtocolConfig {
+    ProtocolConfig {
+        version: ProtocolVersion::V2,
+        signing: Some(SigningConfig {
+            key: key.to_string(),
+            link_id: 1,
+            accept_unsigned: false,
+        }),
+    }
+}
+
+fn mission_request(distance: f64) -> SerpeDialect {
+    let target = north_of_spawn(distance);
+    SerpeDialect::MissionRequest(MissionRequest {
+        target_latitude: encode_degrees(target.latitude),
+        target_longitude: encode_degrees(target.longitude),
+    })
+}
+
+/// Registers and acks the first heartbeat, with both sides on `protocol`.
+async fn registers_and_acks_heartbeats(protocol: ProtocolConfig) {
+    let ground_station = TestGroundStation::bind().await;
+    let mut simulator = Simulator::with_protocol(ground_station.address(), protocol.clone());
+
+    let script = tokio::spawn(async move {
+        // Every frame from the drone goes through the same checks as ours
+        let mut link = ground_station.accept_with(protocol).await;
+        link.register().await;
+        link.recv_until(|message| matches!(message, SerpeDialect::Heartbeat(_)))
+            .await;
+        link.send(SerpeDialect::HeartbeatAck(HeartbeatAck {})).await;
+        link
+    });
+
+    let entity = simulator.drone();
+    simulator
+        .run_until(|world| {
+            world
+                .get::<Connection>(entity)
+                .is_some_and(|connection| connection.health.last_ack_received.is_some())
+        })
+        .await;
+    let _link = script.await.unwrap();
+
+    let connection = simulator.app.world().get::<Connection>(entity).unwrap();
+    assert_eq!(connection.system_id, SYSTEM_ID);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mavlink_1() {
+    registers_and_acks_heartbeats(ProtocolConfig {
+        version: ProtocolVersion::V1,
+        signing: None,
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn signed_mavlink_2() {
+    registers_and_acks_heartbeats(signed(KEY)).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unsigned_register_ack_fails_the_handshake() {
+    let ground_station = TestGroundStation::bind().await;
+    let mut simulator = Simulator::with_protocol(ground_station.address(), signed(KEY));
+
+    let script = tokio::spawn(async move {
+        let mut link = ground_station.accept().await;
+        link.register().await;
+        link
+    });
+
+    let entity = simulator.drone();
+    simulator
+        .run_until(|world| world.entity(entity).contains::<FailedConnection>())
+        .await;
+    let _link = script.await.unwrap();
+
+    let failed = simulator
+        .app
+        .world()
+        .get::<FailedConnection>(entity)
+        .unwrap();
+    assert!(matches!(
+        failed.error,
+        IoError::Rejected(Rejection::Unsigned)
+    ));
+}
+
+/// Flies a first mission, then sends the drone another one three ways it
+/// must turn down: the first mission's frame again, unsigned and signed with
+/// the wrong key. Only the properly signed request after them is answered.
+#[tokio::test(flavor = "multi_thread")]
+async fn drone_drops_replayed_unsigned_and_forged_frames() {
+    let ground_station = TestGroundStation::bind().await;
+    let mut simulator = Simulator::with_protocol(ground_station.address(), signed(KEY));
+    let (idle_sender, idle) = oneshot::channel();
+
+    let script = tokio::spawn(async move {
+        let mut link = ground_station.accept_with(signed(KEY)).await;
+        link.register().await;
+
+        let first = mission_request(MISSION_DISTANCE);
+        let first_frame = link.frame(&first);
+        link.send_frame(first_frame.clone(), first.clone()).await;
+        link.recv_until(|message| matches!(message, SerpeDialect::MissionAccept(_)))
+            .await;
+        link.send(SerpeDialect::MissionAcceptAck(MissionAcceptAck {}))
+            .await;
+        link.recv_until(|message| matches!(message, SerpeDialect::MissionFinished(_)))
+            .await;
+        link.send(SerpeDialect::MissionFinishedAck(MissionFinishedAck {}))
+            .await;
+        idle.await.unwrap();
+
+        link.send_frame(first_frame, first).await;
+        let rejected = mission_request(MISSION_DISTANCE * 3.0);
+        for protocol in [ProtocolConfig::default(), signed(OTHER_KEY)] {
+            let mut framer = Framer::new(GROUND_STATION_SYSTEM_ID, &protocol).unwrap();
+            let frame = framer.next_frame(as_message(&rejected)).unwrap();
+            link.send_frame(frame, rejected.clone()).await;
+        }
+        link.send(mission_request(MISSION_DISTANCE * 2.0)).await;
+        link.recv_until(|message| matches!(message, SerpeDialect::MissionAccept(_)))
+            .await;
+        link.send(SerpeDialect::MissionAcceptAck(MissionAcceptAck {}))
+            .await;
+        // Any request that got through would have been answered by now
+        link.recv_until(|message| matches!(message, SerpeDialect::MissionFinished(_)))
+            .await;
+        link.send(SerpeDialect::MissionFinishedAck(MissionFinishedAck {}))
+            .await;
+        link
+    });
+
+    let entity = simulator.drone();
+    let mut flew_first = false;
+    simulator
+        .run_until(|world| {
+            flew_first |= world.entity(entity).contains::<Mission>();
+            flew_first && !world.entity(entity).contains::<Mission>()
+        })
+        .await;
+    idle_sender.send(()).unwrap();
+    simulator
+        .run_until(|world| script.is_finished() && !world.entity(entity).contains::<Mission>())
+        .await;
+    let link = script.await.unwrap();
+
+    let accepts = link
+        .transcript
+        .iter()
+        .filter(|(_, message)| matches!(message, SerpeDialect::MissionAccept(_)))
+        .count();
+    assert_eq!(accepts, 2);
+
+    let drone = simulator.app.world().get::<Drone>(entity).unwrap();
+    assert!(
+        drone
+            .coordinates
+            .distance_to(&north_of_spawn(MISSION_DISTANCE * 2.0))
+            < 0.01
+    );
+}
+
+/// The link faults break the drone's own signatures, for the ground station
+/// to catch.
+#[tokio::test(flavor = "multi_thread")]
+async fn link_faults_send_unsigned_and_replayed_frames() {
+    let ground_station = TestGroundStation::bind().await;
+    let spec = DroneSpec {
+        protocol: signed(KEY),
+        faults: vec![
+            FaultSpec {
+                kind: Fault::UnsignedFrames,
+                at: 0.0,
+                until: Some(5.0),
+            },
+            FaultSpec {
+                kind: Fault::ReplayedFrames,
+                at: 5.0,
+                until: None,
+            },
+        ],
+        ..Default::default()
+    };
+    let mut simulator = Simulator::with_drone(ground_station.address(), spec);
+
+    let script = tokio::spawn(async move {
+        let mut link = ground_station.accept_with(signed(KEY)).await;
+        link.register().await;
+
+        let mut rejections = Vec::new();
+        while !rejections.contains(&Rejection::Replayed) {
+            let frame = link.recv_frame().await;
+            if let Err(rejection) = link.verify(&frame) {
+                if rejections.last() != Some(&rejection) {
+                    rejections.push(rejection);
+                }
+            }
+        }
+        rejections
+    });
+
+    simulator.run_until(|_| script.is_finished()).await;
+    let rejections = script.await.unwrap();
+    assert_eq!(rejections, [Rejection::Unsigned, Rejection::Replayed]);
+}
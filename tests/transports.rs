@@ -0,0 +1,267 @@
+//! The same handshake over every transport a drone can use. Past the
+//! handshake the transports are interchangeable, `mission_flow` covers the
+//! rest over TCP.
+
+mod common;
+
+use common::{
+    as_message, sequence, Simulator, TestGroundStation, AGENT_ID, RECV_TIMEOUT, SYSTEM_ID,
+};
+use mavio::{AsyncReceiver, AsyncSender};
+use simulator::{
+    domain::{connection::Connection, coordinates::DEFAULT_COORDINATES},
+    io::{
+        protocol::{Framer, ProtocolConfig},
+        transport::{
+            shared::{SharedPorts, SharedTcpServer},
+            Transport, TransportConfig,
+        },
+        wire,
+    },
+    mavlink::dialects::{
+        serpe_dialect::messages::{HeartbeatAck, RegisterAck},
+        SerpeDialect,
+    },
+    scenario::{DroneSpec, Scenario, ServerSpec},
+};
+use tokio_util::sync::CancellationToken;
+
+/// Registers the drone, then waits for the first heartbeat to be acked so
+/// both directions are known to work after the handshake.
+async fn registers_and_acks_heartbeats(
+    ground_station: TestGroundStation,
+    mut simulator: Simulator,
+) {
+    let script = tokio::spawn(async move {
+        let mut link = ground_station.accept().await;
+        link.register().await;
+        link.recv_until(|message| matches!(message, SerpeDialect::Heartbeat(_)))
+            .await;
+        link.send(SerpeDialect::HeartbeatAck(HeartbeatAck {})).await;
+        link
+    });
+
+    let entity = simulator.drone();
+    simulator
+        .run_until(|world| {
+            world
+                .get::<Connection>(entity)
+                .is_some_and(|connection| connection.health.last_ack_received.is_some())
+        })
+        .await;
+    let link = script.await.unwrap();
+
+    let connection = simulator.app.world().get::<Connection>(entity).unwrap();
+    assert_eq!(connection.system_id, SYSTEM_ID);
+    assert_eq!(
+        sequence(&link.transcript, &[])[..4],
+        ["Register", "RegisterAck", "Heartbeat", "HeartbeatAck"]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tcp_client() {
+    let ground_station = TestGroundStation::bind().await;
+    let simulator = Simulator::new(ground_station.address());
+    registers_and_acks_heartbeats(ground_station, simulator).await;
+}
+
+/// A port nothing is listening on, for the drones to listen on instead.
+fn free_address() -> String {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tcp_server() {
+    let address = free_address();
+    let ground_station = TestGroundStation::dial(address.clone());
+    let simulator = Simulator::with_transport(
+        ground_station.address(),
+        TransportConfig::TcpServer {
+            address,
+            shared: false,
+        },
+    );
+    registers_and_acks_heartbeats(ground_station, simulator).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tcp_server_shared_by_every_drone() {
+    let address = free_address();
+    let agent_ids = [AGENT_ID, AGENT_ID + 1, AGENT_ID + 2];
+    let scenario = Scenario {
+        server: Some(ServerSpec {
+            address: address.clone(),
+            shared: true,
+        }),
+        drones: agent_ids
+            .iter()
+            .map(|agent_id| DroneSpec {
+                agent_id: Some(*agent_id),
+                connect: true,
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let ground_station = TestGroundStation::dial(address);
+    let mut simulator = Simulator::with_scenario(ground_station.address(), scenario);
+
+    let script = tokio::spawn(async move {
+        let mut link = ground_station.accept().await;
+        let mut registered = Vec::new();
+        let mut heartbeats_from = Vec::new();
+
+        // Hand out ids in the order the drones register, acking heartbeats
+        // one by one to make sure each reaches the right drone
+        while heartbeats_from.len() < agent_ids.len() {
+            match link.recv_from().await {
+                (_, SerpeDialect::Register(register)) => {
+                    let system_id = SYSTEM_ID + registered.len() as u8;
+                    registered.push((register.agent_id, system_id));
+                    link.send(SerpeDialect::RegisterAck(RegisterAck { system_id }))
+                        .await;
+                }
+                (system_id, SerpeDialect::Heartbeat(_))
+                    if !heartbeats_from.contains(&system_id) =>
+                {
+                    heartbeats_from.push(system_id);
+                    link.send_to(system_id, SerpeDialect::HeartbeatAck(HeartbeatAck {}))
+                        .await;
+                }
+                _ => {}
+            }
+        }
+        (link, registered)
+    });
+
+    simulator
+        .run_until(|world| {
+            let mut query = world.query::<&Connection>();
+            let acked = query
+                .iter(world)
+                .filter(|connection| connection.health.last_ack_received.is_some())
+                .count();
+            acked == agent_ids.len()
+        })
+        .await;
+    let (_link, registered) = script.await.unwrap();
+
+    for (agent_id, system_id) in registered {
+        let entity = simulator.agent(agent_id);
+        let connection = simulator.app.world().get::<Connection>(entity).unwrap();
+        assert_eq!(connection.system_id, system_id);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn udp() {
+    let ground_station = TestGroundStation::bind_udp().await;
+    let simulator = Simulator::with_transport(
+        ground_station.address(),
+        TransportConfig::Udp {
+            bind: "127.0.0.1:0".to_string(),
+        },
+    );
+    registers_and_acks_heartbeats(ground_station, simulator).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn memory() {
+    let ground_station = TestGroundStation::bind_memory("transports-memory");
+    let simulator = Simulator::with_transport(ground_station.address(), TransportConfig::Memory);
+    registers_and_acks_heartbeats(ground_station, simulator).await;
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn pty() {
+    let link = std::env::temp_dir().join(format!("serpe-drone-{}", std::process::id()));
+
+    let ground_station = TestGroundStation::open_pty(link.clone());
+    let simulator = Simulator::with_transport(
+        ground_station.address(),
+        TransportConfig::Pty { link: Some(link) },
+    );
+    registers_and_acks_heartbeats(ground_station, simulator).await;
+}
+
+/// A drone on a shared port that stops reading is dropped from it, instead
+/// of holding up the frames for every other drone.
+#[tokio::test(flavor = "multi_thread")]
+async fn tcp_server_shared_drops_a_drone_that_stops_reading() {
+    // Well past what the port buffers for one drone
+    const FLOOD: usize = 20_000;
+
+    let address = free_address();
+    let ports = SharedPorts::new(CancellationToken::new());
+    let join = |ports| {
+        SharedTcpServer {
+            address: address.clone(),
+            ports,
+        }
+        .open()
+    };
+    let ground_station = TestGroundStation::dial(address.clone());
+    let (stuck, reading, mut link) =
+        tokio::join!(join(ports.clone()), join(ports), ground_station.accept());
+    let (mut stuck_reader, stuck_writer) = stuck.unwrap();
+    let (reader, writer) = reading.unwrap();
+
+    // Registered in this order, the stuck drone gets `SYSTEM_ID`. Their
+    // writers are kept, dropping one leaves the port.
+    let mut senders = Vec::new();
+    for (index, writer) in [stuck_writer, writer].into_iter().enumerate() {
+        let agent_id = AGENT_ID + index as u32;
+        let mut framer = Framer::new(0, &ProtocolConfig::default()).unwrap();
+        let register = framer
+            .next_frame(&wire::register(agent_id, &DEFAULT_COORDINATES))
+            .unwrap();
+        let mut sender = AsyncSender::versionless(writer);
+        sender.send(&register).await.unwrap();
+        sender.flush().await.unwrap();
+        senders.push(sender);
+
+        let message = link.recv().await;
+        assert!(
+            matches!(message, SerpeDialect::Register(register) if register.agent_id == agent_id)
+        );
+        link.send(SerpeDialect::RegisterAck(RegisterAck {
+            system_id: SYSTEM_ID + index as u8,
+        }))
+        .await;
+    }
+
+    let ack = SerpeDialect::HeartbeatAck(HeartbeatAck {});
+    let mut framer = Framer::new(SYSTEM_ID, &ProtocolConfig::default()).unwrap();
+    let flood = framer.next_frame(as_message(&ack)).unwrap();
+    for _ in 0..FLOOD {
+        link.send_frame(flood.clone(), ack.clone()).await;
+    }
+    link.send_to(SYSTEM_ID + 1, ack).await;
+
+    let mut receiver = AsyncReceiver::versionless(reader);
+    let acked = async {
+        loop {
+            let frame = receiver.recv().await.unwrap();
+            if let Ok(SerpeDialect::HeartbeatAck(_)) = frame.decode() {
+                break;
+            }
+        }
+    };
+    tokio::time::timeout(RECV_TIMEOUT, acked)
+        .await
+        .expect("the other drone was held up");
+
+    // What was buffered for the stuck drone is all it gets before the end
+    let mut sink = tokio::io::sink();
+    let dropped = tokio::io::copy(&mut stuck_reader, &mut sink);
+    tokio::time::timeout(RECV_TIMEOUT, dropped)
+        .await
+        .expect("the stuck drone is still on the port")
+        .unwrap();
+}